@@ -1,155 +1,209 @@
-use ab_glyph::{Font, FontRef, PxScale};
-use image::{ImageBuffer, ImageEncoder, Rgba, RgbaImage};
-use std::collections::HashMap;
+use ab_glyph::FontRef;
+use image::ImageEncoder;
+use serde::Deserialize;
 use std::fs::File;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[path = "src/atlas_builder.rs"]
+#[allow(dead_code)]
+mod atlas_builder;
+#[path = "src/charsets.rs"]
+#[allow(dead_code)]
+mod charsets;
+
+use atlas_builder::AtlasLayout;
+
+const DEFAULT_MANIFEST: &str = "font/atlas.toml";
+
+// Embedded atlas description read from font/atlas.toml
+#[derive(Deserialize)]
+struct Manifest {
+    font: PathBuf,
+    #[serde(default)]
+    charsets: Vec<String>,
+    #[serde(default)]
+    ranges: Vec<String>,
+    glyph_size: u32,
+    padding: u32,
+    atlas_size: u32,
+}
+
+// Read an environment override, failing the build if it doesn't parse
+fn env_override<T: FromStr>(name: &str) -> Option<T> {
+    println!("cargo:rerun-if-env-changed={}", name);
+    let value = std::env::var(name).ok()?;
+    match value.trim().parse() {
+        Ok(parsed) => Some(parsed),
+        Err(_) => panic!("Invalid value for {}: {:?}", name, value),
+    }
+}
 
 fn main() {
-    let font_path = "font/PleckJP-Regular.ttf";
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/atlas_builder.rs");
+    println!("cargo:rerun-if-changed=src/charsets.rs");
+
     let out_dir = std::env::var("OUT_DIR").unwrap();
     let atlas_path = Path::new(&out_dir).join("font_atlas.rs");
+    // The charset on its own, for matrix-atlas, which has no use for the pages
+    let charset_path = Path::new(&out_dir).join("charset.rs");
+
+    // Load manifest, then apply environment overrides
+    let manifest_path: PathBuf =
+        env_override("MATRIX_ATLAS_MANIFEST").unwrap_or_else(|| PathBuf::from(DEFAULT_MANIFEST));
+    println!("cargo:rerun-if-changed={}", manifest_path.display());
+    let manifest_text = std::fs::read_to_string(&manifest_path)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {}", manifest_path.display(), e));
+    let manifest: Manifest = toml::from_str(&manifest_text)
+        .unwrap_or_else(|e| panic!("Failed to parse {}: {}", manifest_path.display(), e));
+
+    let font_path: PathBuf = env_override("MATRIX_ATLAS_FONT").unwrap_or_else(|| {
+        // Manifest font paths are relative to the manifest
+        manifest_path.parent().unwrap_or(Path::new(".")).join(&manifest.font)
+    });
+    let list = |names: String| {
+        let names = names.split(',').filter(|name| !name.trim().is_empty());
+        names.map(str::to_owned).collect::<Vec<_>>()
+    };
+    let charsets: Vec<String> = env_override("MATRIX_ATLAS_CHARSETS")
+        .map(list)
+        .unwrap_or(manifest.charsets);
+    let ranges: Vec<String> = env_override("MATRIX_ATLAS_RANGES")
+        .map(list)
+        .unwrap_or(manifest.ranges);
+    let atlas_size = env_override("MATRIX_ATLAS_SIZE").unwrap_or(manifest.atlas_size);
+    let layout = AtlasLayout {
+        width: atlas_size,
+        height: atlas_size,
+        glyph_size: env_override("MATRIX_ATLAS_GLYPH_SIZE").unwrap_or(manifest.glyph_size),
+        padding: env_override("MATRIX_ATLAS_PADDING").unwrap_or(manifest.padding),
+    };
+    println!("cargo:rerun-if-changed={}", font_path.display());
 
-    // Half-width katakana: U+FF66 to U+FF9D (58 characters)
-    let charset: String = (0xFF66..=0xFF9D)
-        .filter_map(char::from_u32)
-        .collect();
+    let charset = charsets::combine(&charsets, "", &ranges)
+        .unwrap_or_else(|e| panic!("Invalid atlas charset: {}", e));
+    if charset.is_empty() {
+        panic!("Atlas charsets {:?} and ranges {:?} contain no characters", charsets, ranges);
+    }
 
     // Load font
-    let font_data = std::fs::read(font_path).expect("Failed to read font file");
+    let font_data = std::fs::read(&font_path)
+        .unwrap_or_else(|e| panic!("Failed to read font file {}: {}", font_path.display(), e));
     let font = FontRef::try_from_slice(&font_data).expect("Failed to parse font");
 
-    // Atlas configuration
-    const ATLAS_WIDTH: u32 = 2048;
-    const ATLAS_HEIGHT: u32 = 2048;
-    const GLYPH_SIZE: u32 = 32;
-    const PADDING: u32 = 4;
-
     // Create atlas
-    let mut atlas: RgbaImage = ImageBuffer::new(ATLAS_WIDTH, ATLAS_HEIGHT);
-    
-    // Fill with black background
-    for pixel in atlas.pixels_mut() {
-        *pixel = Rgba([0, 0, 0, 0]); // Transparent black
-    }
-
-    let mut glyph_map: HashMap<char, (f32, f32, f32, f32)> = HashMap::new();
-    let scale = PxScale::from(GLYPH_SIZE as f32);
-
-    let mut current_x = PADDING;
-    let mut current_y = PADDING;
-    let mut glyph_count = 0;
-    let mut failed_count = 0;
-    let mut failed_chars = Vec::new();
-
-    for ch in charset.chars() {
-        // Check if we need to move to next row
-        if current_x + GLYPH_SIZE + PADDING > ATLAS_WIDTH {
-            current_x = PADDING;
-            current_y += GLYPH_SIZE + PADDING;
-
-            if current_y + GLYPH_SIZE + PADDING > ATLAS_HEIGHT {
-                eprintln!("Warning: Font atlas full, skipping remaining characters");
-                break;
-            }
+    let atlas = atlas_builder::rasterize_atlas(&[font], &charset, &layout)
+        .unwrap_or_else(|e| panic!("Invalid atlas layout in {}: {}", manifest_path.display(), e));
+    let glyph_count = atlas.glyph_map.len();
+
+    // An atlas with gaps would silently drop characters at runtime
+    if !atlas.failed_chars.is_empty() {
+        eprintln!(
+            "{} does not cover {} of the {} requested characters:",
+            font_path.display(),
+            atlas.failed_chars.len(),
+            charset.len()
+        );
+        for ch in &atlas.failed_chars {
+            eprintln!("  - {:?} (U+{:04X})", ch, *ch as u32);
         }
-
-        // Rasterize glyph
-        let glyph_id = font.glyph_id(ch);
-        let glyph = glyph_id.with_scale_and_position(scale, ab_glyph::Point { x: 0.0, y: 0.0 });
-
-        if let Some(outlined) = font.outline_glyph(glyph) {
-            // Create glyph bitmap
-            let mut glyph_img: RgbaImage = ImageBuffer::new(GLYPH_SIZE, GLYPH_SIZE);
-            for pixel in glyph_img.pixels_mut() {
-                *pixel = Rgba([0, 0, 0, 0]);
-            }
-
-            // Rasterize outline
-            outlined.draw(|x: u32, y: u32, coverage: f32| {
-                if x < GLYPH_SIZE && y < GLYPH_SIZE {
-                    let alpha = (coverage * 255.0) as u8;
-                    *glyph_img.get_pixel_mut(x, y) = Rgba([255, 255, 255, alpha]);
-                }
-            });
-
-            // Copy to atlas
-            for y in 0..GLYPH_SIZE {
-                for x in 0..GLYPH_SIZE {
-                    let src = *glyph_img.get_pixel(x, y);
-                    let dst_x = current_x + x;
-                    let dst_y = current_y + y;
-                    
-                    if dst_x < ATLAS_WIDTH && dst_y < ATLAS_HEIGHT {
-                        *atlas.get_pixel_mut(dst_x, dst_y) = src;
-                    }
-                }
-            }
-
-            glyph_count += 1;
-
-            // Store glyph metrics (normalized UV coordinates) - ONLY for successfully rasterized glyphs
-            let u_min = current_x as f32 / ATLAS_WIDTH as f32;
-            let v_min = current_y as f32 / ATLAS_HEIGHT as f32;
-            let u_max = (current_x + GLYPH_SIZE) as f32 / ATLAS_WIDTH as f32;
-            let v_max = (current_y + GLYPH_SIZE) as f32 / ATLAS_HEIGHT as f32;
-
-            glyph_map.insert(ch, (u_min, v_min, u_max, v_max));
-        } else {
-            // Character failed to rasterize
-            failed_count += 1;
-            failed_chars.push(ch);
-        }
-
-        current_x += GLYPH_SIZE + PADDING;
+        panic!(
+            "Font atlas is incomplete; fix the charsets and ranges in {} or MATRIX_ATLAS_*",
+            manifest_path.display()
+        );
     }
 
-    // Encode atlas as PNG to bytes
-    let png_bytes = {
-        let mut buffer = Vec::new();
-        let encoder = image::codecs::png::PngEncoder::new(&mut buffer);
-        encoder
-            .write_image(
-                atlas.as_raw(),
-                ATLAS_WIDTH,
-                ATLAS_HEIGHT,
-                image::ColorType::Rgba8,
-            )
-            .expect("Failed to encode PNG");
-        buffer
-    };
+    // Encode each atlas page as PNG bytes
+    let png_pages: Vec<Vec<u8>> = atlas
+        .pages
+        .iter()
+        .map(|page| {
+            let mut buffer = Vec::new();
+            let encoder = image::codecs::png::PngEncoder::new(&mut buffer);
+            encoder
+                .write_image(
+                    page.as_raw(),
+                    layout.width,
+                    layout.height,
+                    image::ColorType::Rgba8,
+                )
+                .expect("Failed to encode PNG");
+            buffer
+        })
+        .collect();
+    let png_size: usize = png_pages.iter().map(Vec::len).sum();
 
-    println!("Generated font atlas with {} glyphs in PNG ({} bytes)", glyph_count, png_bytes.len());
+    println!(
+        "Generated font atlas with {} glyphs in {} PNG page(s) ({} bytes)",
+        glyph_count,
+        png_pages.len(),
+        png_size
+    );
 
     // Generate Rust code with embedded atlas and glyph map
     let mut output = String::new();
     output.push_str("// Auto-generated font atlas - do not edit\n\n");
-    output.push_str("pub const FONT_ATLAS_PNG: &[u8] = &[\n");
-
-    // Write PNG bytes as hex
-    for (i, byte) in png_bytes.iter().enumerate() {
-        if i % 16 == 0 {
-            output.push_str("    ");
+    output.push_str("pub const FONT_ATLAS_PAGES: &[&[u8]] = &[\n");
+
+    // Write PNG bytes as hex, one slice per texture array layer
+    for png_bytes in &png_pages {
+        output.push_str("    &[\n");
+        for (i, byte) in png_bytes.iter().enumerate() {
+            if i % 16 == 0 {
+                output.push_str("        ");
+            }
+            output.push_str(&format!("0x{:02x}, ", byte));
+            if (i + 1) % 16 == 0 {
+                output.push('\n');
+            }
         }
-        output.push_str(&format!("0x{:02x}, ", byte));
-        if (i + 1) % 16 == 0 {
+        if png_bytes.len() % 16 != 0 {
             output.push('\n');
         }
-    }
-    if png_bytes.len() % 16 != 0 {
-        output.push('\n');
+        output.push_str("    ],\n");
     }
     output.push_str("];\n\n");
 
+    // Embed the font itself so runtime fallback chains can start from it
+    let font_abs_path = std::fs::canonicalize(&font_path).expect("Failed to resolve font path");
+    output.push_str(&format!(
+        "pub const FONT_DATA: &[u8] = include_bytes!({:?});\n\n",
+        font_abs_path
+    ));
+
+    // Write atlas-wide metrics
+    let charset_string: String = charset.iter().collect();
+    std::fs::write(
+        &charset_path,
+        format!("pub const CHARSET: &str = {:?};\n", charset_string),
+    )
+    .expect("Failed to write charset file");
+    output.push_str(&format!("pub const GLYPH_SIZE: u32 = {};\n", layout.glyph_size));
+    output.push_str(&format!("pub const GLYPH_PADDING: u32 = {};\n", layout.padding));
+    output.push_str(&format!("pub const GLYPH_ASCENT: f32 = {:?};\n\n", atlas.ascent));
+
     // Write glyph map
-    output.push_str("pub fn get_glyph_map() -> std::collections::HashMap<char, (f32, f32, f32, f32)> {\n");
+    output.push_str("pub fn get_glyph_map() -> std::collections::HashMap<char, GlyphMetrics> {\n");
     output.push_str("    let mut map = std::collections::HashMap::new();\n");
 
-    for (ch, (u_min, v_min, u_max, v_max)) in &glyph_map {
+    for (ch, m) in &atlas.glyph_map {
         let ch_escaped = format!("{:?}", ch);
         output.push_str(&format!(
-            "    map.insert({}, ({}, {}, {}, {}));\n",
-            ch_escaped, u_min, v_min, u_max, v_max
+            "    map.insert({}, GlyphMetrics {{ u_min: {:?}, v_min: {:?}, u_max: {:?}, v_max: {:?}, \
+             layer: {}, width: {}, height: {}, bearing_x: {:?}, bearing_y: {:?}, advance: {:?} }});\n",
+            ch_escaped,
+            m.u_min,
+            m.v_min,
+            m.u_max,
+            m.v_max,
+            m.layer,
+            m.width,
+            m.height,
+            m.bearing_x,
+            m.bearing_y,
+            m.advance
         ));
     }
 
@@ -163,10 +217,4 @@ fn main() {
 
     // Print summary
     println!("cargo:warning=Font atlas generated: {} glyphs successfully rasterized", glyph_count);
-    if failed_count > 0 {
-        println!("cargo:warning=WARNING: {} glyphs failed to rasterize:", failed_count);
-        for ch in &failed_chars {
-            println!("cargo:warning=  - '{}' (U+{:04X})", ch, *ch as u32);
-        }
-    }
 }
@@ -0,0 +1,58 @@
+// Background image or video frame, drawn behind the rain
+struct BackgroundUniforms {
+    // Share of the image visible across the screen, keeping its aspect ratio
+    uv_scale: vec2f,
+    // Pan position, in texture coordinates
+    uv_offset: vec2f,
+    // Blur radius, in texture coordinates
+    blur: vec2f,
+    dim: f32,
+    _padding: f32,
+};
+
+@group(0) @binding(0) var background_texture: texture_2d<f32>;
+@group(0) @binding(1) var background_sampler: sampler;
+@group(0) @binding(2) var<uniform> uniforms: BackgroundUniforms;
+
+// Taps of the blur disc, spread along a golden-angle spiral
+const BLUR_TAPS: i32 = 32;
+const GOLDEN_ANGLE: f32 = 2.39996323;
+
+struct VertexOutput {
+    @builtin(position) position: vec4f,
+    @location(0) uv: vec2f,
+};
+
+// A single triangle covering the screen
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let corner = vec2f(f32((index << 1u) & 2u), f32(index & 2u));
+    let screen_uv = vec2f(corner.x, 1.0 - corner.y);
+
+    var output: VertexOutput;
+    output.position = vec4f(corner * 2.0 - 1.0, 0.0, 1.0);
+    output.uv = 0.5 + (screen_uv - 0.5) * uniforms.uv_scale + uniforms.uv_offset;
+    return output;
+}
+
+@fragment
+fn fs_main(input: VertexOutput) -> @location(0) vec4f {
+    var color = textureSampleLevel(background_texture, background_sampler, input.uv, 0.0).rgb;
+
+    if (any(uniforms.blur > vec2f(0.0))) {
+        for (var i = 1; i < BLUR_TAPS; i++) {
+            let radius = sqrt(f32(i) / f32(BLUR_TAPS));
+            let angle = f32(i) * GOLDEN_ANGLE;
+            let offset = vec2f(cos(angle), sin(angle)) * radius * uniforms.blur;
+            color += textureSampleLevel(
+                background_texture,
+                background_sampler,
+                input.uv + offset,
+                0.0,
+            ).rgb;
+        }
+        color /= f32(BLUR_TAPS);
+    }
+
+    return vec4f(color * (1.0 - uniforms.dim), 1.0);
+}
@@ -49,35 +49,119 @@ fn cs_update_rain(@builtin(global_invocation_id) global_id: vec3u) {
 
 struct VertexInput {
     @location(0) position: vec2f,
-    @location(1) uv: vec2f,
+    @location(1) corner: vec2f,
     @location(2) color: vec4f,
+    @location(3) layer: u32,
+    @location(4) flags: u32,
+    @location(5) uv_rect: vec4f,
+    @location(6) prev_corner: vec2f,
+    @location(7) prev_uv_rect: vec4f,
+    @location(8) prev_layer: u32,
+    @location(9) prev_flags: u32,
+    @location(10) blend: f32,
 };
 
 struct VertexOutput {
     @builtin(position) position: vec4f,
     @location(0) color: vec4f,
     @location(1) uv: vec2f,
+    @location(2) @interpolate(flat) layer: u32,
+    @location(3) @interpolate(flat) flags: u32,
+    @location(4) @interpolate(flat) uv_rect: vec4f,
+    @location(5) prev_uv: vec2f,
+    @location(6) @interpolate(flat) prev_layer: u32,
+    @location(7) @interpolate(flat) prev_flags: u32,
+    @location(8) @interpolate(flat) prev_uv_rect: vec4f,
+    @location(9) @interpolate(flat) blend: f32,
 };
 
+// Vertex flags (VERTEX_* in renderer.rs)
+const KEEP_COLORS: u32 = 1u;
+const FLIP_X: u32 = 2u;
+const FLIP_Y: u32 = 4u;
+const ROTATE_90: u32 = 8u;
+
+// Map a quad corner to the point of the upright glyph drawn there, undoing the
+// quarter turn and then the flips
+fn glyph_corner(corner: vec2f, flags: u32) -> vec2f {
+    var local = corner;
+    if ((flags & ROTATE_90) != 0u) {
+        local = vec2f(local.y, 1.0 - local.x);
+    }
+    if ((flags & FLIP_X) != 0u) {
+        local.x = 1.0 - local.x;
+    }
+    if ((flags & FLIP_Y) != 0u) {
+        local.y = 1.0 - local.y;
+    }
+    return local;
+}
+
 @vertex
 fn vs_main(input: VertexInput) -> VertexOutput {
     var output: VertexOutput;
     output.position = vec4f(input.position, 0.0, 1.0);
     output.color = input.color;
-    output.uv = input.uv;
+    output.uv = mix(input.uv_rect.xy, input.uv_rect.zw, glyph_corner(input.corner, input.flags));
+    output.layer = input.layer;
+    output.flags = input.flags;
+    output.uv_rect = input.uv_rect;
+    let prev_corner = glyph_corner(input.prev_corner, input.prev_flags);
+    output.prev_uv = mix(input.prev_uv_rect.xy, input.prev_uv_rect.zw, prev_corner);
+    output.prev_layer = input.prev_layer;
+    output.prev_flags = input.prev_flags;
+    output.prev_uv_rect = input.prev_uv_rect;
+    output.blend = input.blend;
     return output;
 }
 
-@group(0) @binding(0) var glyph_texture: texture_2d<f32>;
+@group(0) @binding(0) var glyph_texture: texture_2d_array<f32>;
 @group(0) @binding(1) var tex_sampler: sampler;
 
+// Tint a glyph texel with the vertex color, premultiplied by alpha. The quad
+// may be larger than the glyph when cross-fading, so texels outside its
+// rectangle are cleared.
+fn shade(texel: vec4f, color: vec4f, flags: u32, uv: vec2f, uv_rect: vec4f) -> vec4f {
+    // Sprites may keep their own colors; the vertex alpha still fades them
+    var shaded = color * texel;
+    if ((flags & KEEP_COLORS) != 0u) {
+        shaded = vec4f(texel.rgb, texel.a * color.a);
+    }
+    let inside = all(uv >= uv_rect.xy) && all(uv <= uv_rect.zw);
+    let alpha = select(0.0, shaded.a, inside);
+    return vec4f(shaded.rgb * alpha, alpha);
+}
+
+// Premultiplied color of the fragment, cross-faded from the previous glyph
+fn glyph_color(input: VertexOutput) -> vec4f {
+    // Sample both glyphs in uniform control flow
+    let texel = textureSample(glyph_texture, tex_sampler, input.uv, input.layer);
+    let prev_texel = textureSample(glyph_texture, tex_sampler, input.prev_uv, input.prev_layer);
+
+    // Cross-fade from the previous glyph, blending premultiplied colors
+    let current = shade(texel, input.color, input.flags, input.uv, input.uv_rect);
+    let previous = shade(
+        prev_texel,
+        input.color,
+        input.prev_flags,
+        input.prev_uv,
+        input.prev_uv_rect,
+    );
+    return mix(previous, current, input.blend);
+}
+
+// For the alpha blend mode, which blends straight alpha
 @fragment
 fn fs_main(input: VertexOutput) -> @location(0) vec4f {
-    // Sample the glyph texture
-    let glyph_color = textureSample(glyph_texture, tex_sampler, input.uv);
-    
-    // Multiply by vertex color for green gradient effect
-    let final_color = input.color * glyph_color;
-    
-    return final_color;
+    let blended = glyph_color(input);
+    if (blended.a <= 0.0) {
+        return vec4f(0.0);
+    }
+    return vec4f(blended.rgb / blended.a, blended.a);
+}
+
+// For the additive, screen and premultiplied blend modes
+@fragment
+fn fs_premultiplied(input: VertexOutput) -> @location(0) vec4f {
+    return glyph_color(input);
 }
@@ -0,0 +1,446 @@
+// Glyph atlas rasterization shared by build.rs and the runtime font loader.
+// The build script pulls this file in with #[path], so it may only depend on
+// ab_glyph, image and std - nothing from the rest of the crate.
+
+use ab_glyph::{Font, PxScale, ScaleFont};
+use image::{ImageBuffer, Rgba, RgbaImage};
+use std::collections::HashMap;
+
+// Default atlas configuration
+pub const ATLAS_SIZE: u32 = 2048;
+// Each mip level halves the gap between cells, so 8 texels allow four levels:
+// 32 px glyphs shrink to 4 px and 64 px (2x DPI) glyphs to 8 px, smaller than
+// rain is ever drawn, while padding takes only a fifth of a 32 px cell's stride
+pub const PADDING: u32 = 8;
+// Largest glyph cell a default page fits, padding included
+pub const MAX_GLYPH_SIZE: u32 = ATLAS_SIZE - 2 * PADDING;
+
+// Texture array layers available to the atlas (wgpu's default limit)
+pub const MAX_PAGES: usize = 256;
+
+// Mirrored glyphs live in Supplementary Private Use Area-A, at this base plus
+// the code point of the BMP character they mirror
+pub const MIRROR_BASE: u32 = 0xF0000;
+
+// Page size and cell grid of an atlas
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AtlasLayout {
+    pub width: u32,
+    pub height: u32,
+    pub glyph_size: u32,
+    pub padding: u32,
+}
+
+impl AtlasLayout {
+    pub fn new(glyph_size: u32) -> Self {
+        Self {
+            width: ATLAS_SIZE,
+            height: ATLAS_SIZE,
+            glyph_size,
+            padding: PADDING,
+        }
+    }
+
+    // Whether at least one glyph cell fits on a page
+    pub fn validate(&self) -> Result<(), String> {
+        let fits = self
+            .padding
+            .checked_mul(2)
+            .and_then(|padding| padding.checked_add(self.glyph_size))
+            .is_some_and(|cell| cell <= self.width.min(self.height));
+        if self.glyph_size == 0 {
+            return Err("Glyph size must be at least 1".to_string());
+        }
+        if !fits {
+            return Err(format!(
+                "A {} px glyph with {} texels of padding does not fit a {}x{} atlas page",
+                self.glyph_size, self.padding, self.width, self.height
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GlyphMetrics {
+    // Normalized UV rectangle of the glyph bitmap
+    pub u_min: f32,
+    pub v_min: f32,
+    pub u_max: f32,
+    pub v_max: f32,
+    // Texture array layer (atlas page) holding the bitmap
+    pub layer: u32,
+    // Bitmap size in atlas texels
+    pub width: u32,
+    pub height: u32,
+    // Offset from the pen position on the baseline to the bitmap's top-left corner
+    pub bearing_x: f32,
+    pub bearing_y: f32,
+    pub advance: f32,
+}
+
+pub struct RasterizedAtlas {
+    // One image per texture array layer
+    pub pages: Vec<RgbaImage>,
+    pub glyph_map: HashMap<char, GlyphMetrics>,
+    // Distance from the top of a glyph cell to the baseline, in texels
+    pub ascent: f32,
+    // Characters no font could rasterize
+    pub failed_chars: Vec<char>,
+}
+
+// A single glyph bitmap with its placement metrics
+pub struct RasterizedGlyph {
+    pub bitmap: RgbaImage,
+    pub bearing_x: f32,
+    pub bearing_y: f32,
+    pub advance: f32,
+}
+
+pub fn new_page(layout: &AtlasLayout) -> RgbaImage {
+    // Filled with transparent black
+    ImageBuffer::from_pixel(layout.width, layout.height, Rgba([0, 0, 0, 0]))
+}
+
+// Cell origins of a page, in the order glyphs are packed
+pub fn cell_origins(layout: &AtlasLayout) -> impl Iterator<Item = (u32, u32)> {
+    let AtlasLayout {
+        width,
+        height,
+        glyph_size,
+        padding,
+    } = *layout;
+    let stride = (glyph_size + padding) as usize;
+    let last_x = width.saturating_sub(glyph_size + padding);
+    let last_y = height.saturating_sub(glyph_size + padding);
+    (padding..=last_y)
+        .step_by(stride)
+        .flat_map(move |y| (padding..=last_x).step_by(stride).map(move |x| (x, y)))
+}
+
+// The code point drawing `ch` flipped horizontally
+pub fn mirrored(ch: char) -> Option<char> {
+    let code = ch as u32;
+    if code > 0xFFFF {
+        return None;
+    }
+    char::from_u32(MIRROR_BASE + code)
+}
+
+// The character a mirrored code point flips, if it is one
+fn mirror_source(ch: char) -> Option<char> {
+    let code = (ch as u32).checked_sub(MIRROR_BASE)?;
+    if code > 0xFFFF {
+        return None;
+    }
+    char::from_u32(code)
+}
+
+// Rasterize one character from the first font in the fallback chain that covers it.
+// The bitmap is clipped to a glyph_size cell.
+pub fn rasterize_glyph<F: Font>(fonts: &[F], ch: char, glyph_size: u32) -> Option<RasterizedGlyph> {
+    if let Some(source) = mirror_source(ch) {
+        let glyph = rasterize_glyph(fonts, source, glyph_size)?;
+        // Flip within the advance so the mirrored glyph stays in its cell
+        let width = glyph.bitmap.width() as f32;
+        return Some(RasterizedGlyph {
+            bitmap: image::imageops::flip_horizontal(&glyph.bitmap),
+            bearing_x: glyph.advance - glyph.bearing_x - width,
+            bearing_y: glyph.bearing_y,
+            advance: glyph.advance,
+        });
+    }
+
+    let scale = PxScale::from(glyph_size as f32);
+
+    let (outlined, advance) = fonts.iter().find_map(|font| {
+        let glyph_id = font.glyph_id(ch);
+        // Glyph 0 is .notdef, which would draw a placeholder box
+        if glyph_id.0 == 0 {
+            return None;
+        }
+        let glyph = glyph_id.with_scale_and_position(scale, ab_glyph::Point { x: 0.0, y: 0.0 });
+        let outlined = font.outline_glyph(glyph)?;
+        Some((outlined, font.as_scaled(scale).h_advance(glyph_id)))
+    })?;
+
+    // Pixel bounds relative to the pen position on the baseline, clipped to the cell
+    let bounds = outlined.px_bounds();
+    let width = (bounds.width().ceil() as u32).clamp(1, glyph_size);
+    let height = (bounds.height().ceil() as u32).clamp(1, glyph_size);
+
+    let mut bitmap: RgbaImage = ImageBuffer::from_pixel(width, height, Rgba([0, 0, 0, 0]));
+    outlined.draw(|x: u32, y: u32, coverage: f32| {
+        if x < width && y < height {
+            let alpha = (coverage * 255.0) as u8;
+            bitmap.put_pixel(x, y, Rgba([255, 255, 255, alpha]));
+        }
+    });
+
+    Some(RasterizedGlyph {
+        bitmap,
+        bearing_x: bounds.min.x,
+        bearing_y: bounds.min.y,
+        advance,
+    })
+}
+
+// Whether any font in the fallback chain has a glyph for the character
+pub fn is_covered<F: Font>(fonts: &[F], ch: char) -> bool {
+    let ch = mirror_source(ch).unwrap_or(ch);
+    fonts.iter().any(|font| font.glyph_id(ch).0 != 0)
+}
+
+// Metrics for a glyph bitmap stored at (x, y) on the given page
+pub fn place_glyph(
+    glyph: &RasterizedGlyph,
+    layout: &AtlasLayout,
+    layer: u32,
+    x: u32,
+    y: u32,
+) -> GlyphMetrics {
+    let (width, height) = glyph.bitmap.dimensions();
+    GlyphMetrics {
+        u_min: x as f32 / layout.width as f32,
+        v_min: y as f32 / layout.height as f32,
+        u_max: (x + width) as f32 / layout.width as f32,
+        v_max: (y + height) as f32 / layout.height as f32,
+        layer,
+        width,
+        height,
+        bearing_x: glyph.bearing_x,
+        bearing_y: glyph.bearing_y,
+        advance: glyph.advance,
+    }
+}
+
+// Distance from the top of a glyph cell to the baseline for the primary font
+pub fn ascent<F: Font>(fonts: &[F], glyph_size: u32) -> f32 {
+    let scale = PxScale::from(glyph_size as f32);
+    fonts.first().map_or(0.0, |font| font.as_scaled(scale).ascent())
+}
+
+// Rasterize every character of the charset into a fixed-size cell grid, taking
+// each glyph from the first font in the fallback chain that covers it. When a
+// page fills up, packing continues on a new texture array layer.
+pub fn rasterize_atlas<F: Font>(
+    fonts: &[F],
+    charset: &[char],
+    layout: &AtlasLayout,
+) -> Result<RasterizedAtlas, String> {
+    layout.validate()?;
+    let mut pages = vec![new_page(layout)];
+    let mut glyph_map = HashMap::new();
+    let mut failed_chars = Vec::new();
+    let mut cells = cell_origins(layout).collect::<Vec<_>>().into_iter();
+
+    for (i, &ch) in charset.iter().enumerate() {
+        let Some(glyph) = rasterize_glyph(fonts, ch, layout.glyph_size) else {
+            // Character failed to rasterize; it takes no cell
+            failed_chars.push(ch);
+            continue;
+        };
+
+        // Move to the next page once this one is full
+        let (x, y) = match cells.next() {
+            Some(cell) => cell,
+            None => {
+                if pages.len() == MAX_PAGES {
+                    eprintln!("Warning: Font atlas full, skipping remaining characters");
+                    failed_chars.extend_from_slice(&charset[i..]);
+                    break;
+                }
+                pages.push(new_page(layout));
+                cells = cell_origins(layout).collect::<Vec<_>>().into_iter();
+                cells.next().ok_or("Glyph size larger than an atlas page")?
+            }
+        };
+
+        // Copy the bitmap into its atlas cell
+        let layer = pages.len() - 1;
+        image::imageops::replace(&mut pages[layer], &glyph.bitmap, x as i64, y as i64);
+
+        // Store glyph metrics - ONLY for successfully rasterized glyphs
+        glyph_map.insert(ch, place_glyph(&glyph, layout, layer as u32, x, y));
+    }
+
+    Ok(RasterizedAtlas {
+        pages,
+        glyph_map,
+        ascent: ascent(fonts, layout.glyph_size),
+        failed_chars,
+    })
+}
+
+// Parse code point specs like "0041", "U+0041" or "0041-005A" into characters
+pub fn parse_ranges<S: AsRef<str>>(specs: &[S]) -> Result<Vec<char>, String> {
+    let parse_code_point = |text: &str| {
+        let hex = text.trim().trim_start_matches("U+").trim_start_matches("0x");
+        u32::from_str_radix(hex, 16).map_err(|_| format!("Invalid code point {:?}", text))
+    };
+
+    let mut charset = Vec::new();
+    for spec in specs {
+        let spec = spec.as_ref();
+        let (start, end) = match spec.split_once('-') {
+            Some((start, end)) => (parse_code_point(start)?, parse_code_point(end)?),
+            None => (parse_code_point(spec)?, parse_code_point(spec)?),
+        };
+        for ch in (start..=end).filter_map(char::from_u32) {
+            if !charset.contains(&ch) {
+                charset.push(ch);
+            }
+        }
+    }
+    Ok(charset)
+}
+
+// Atlas description stored next to its page PNGs
+pub struct AtlasMetrics {
+    pub layout: AtlasLayout,
+    pub ascent: f32,
+    // Page image file names, relative to the metrics file
+    pub pages: Vec<String>,
+    pub glyph_map: HashMap<char, GlyphMetrics>,
+}
+
+// Serialize atlas metrics as one "key=value" record per line:
+//
+//   atlas width=2048 height=2048 glyph_size=32 padding=8 ascent=26.1
+//   page id=0 file="atlas-0.png"
+//   glyph char=0x41 layer=0 x=4 y=4 width=16 height=21 bearing_x=1 bearing_y=-21 advance=18.2
+pub fn write_metrics(metrics: &AtlasMetrics) -> String {
+    let layout = &metrics.layout;
+    let mut output = String::from("# Glyph atlas metrics - generated by matrix-atlas\n");
+    output.push_str(&format!(
+        "atlas width={} height={} glyph_size={} padding={} ascent={}\n",
+        layout.width, layout.height, layout.glyph_size, layout.padding, metrics.ascent
+    ));
+
+    for (id, file) in metrics.pages.iter().enumerate() {
+        output.push_str(&format!("page id={} file={:?}\n", id, file));
+    }
+
+    let mut glyphs: Vec<_> = metrics.glyph_map.iter().collect();
+    glyphs.sort_by_key(|(ch, _)| **ch);
+    for (ch, m) in glyphs {
+        output.push_str(&format!(
+            "glyph char=0x{:X} layer={} x={} y={} width={} height={} \
+             bearing_x={} bearing_y={} advance={}\n",
+            *ch as u32,
+            m.layer,
+            (m.u_min * layout.width as f32).round() as u32,
+            (m.v_min * layout.height as f32).round() as u32,
+            m.width,
+            m.height,
+            m.bearing_x,
+            m.bearing_y,
+            m.advance
+        ));
+    }
+    output
+}
+
+// Split a "tag key=value key=\"quoted value\"" record into its tag and fields
+pub fn parse_record(line: &str) -> (&str, HashMap<&str, &str>) {
+    let (tag, mut rest) = line.split_once(' ').unwrap_or((line, ""));
+    let mut fields = HashMap::new();
+
+    while let Some((key, after)) = rest.trim_start().split_once('=') {
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => after.split_once(' ').unwrap_or((after, "")),
+        };
+        fields.insert(key.trim(), value);
+        rest = next;
+    }
+    (tag, fields)
+}
+
+pub fn field<T: std::str::FromStr>(fields: &HashMap<&str, &str>, key: &str) -> Result<T, String> {
+    let value = fields.get(key).ok_or_else(|| format!("Missing field {:?}", key))?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {:?}: {:?}", key, value))
+}
+
+fn char_field(fields: &HashMap<&str, &str>) -> Result<char, String> {
+    let value = fields.get("char").ok_or("Missing field \"char\"")?;
+    u32::from_str_radix(value.trim_start_matches("0x"), 16)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or_else(|| format!("Invalid glyph character {:?}", value))
+}
+
+fn layout_fields(fields: &HashMap<&str, &str>) -> Result<(AtlasLayout, f32), String> {
+    let layout = AtlasLayout {
+        width: field(fields, "width")?,
+        height: field(fields, "height")?,
+        glyph_size: field(fields, "glyph_size")?,
+        padding: field(fields, "padding")?,
+    };
+    Ok((layout, field(fields, "ascent")?))
+}
+
+fn glyph_fields(
+    fields: &HashMap<&str, &str>,
+    layout: &AtlasLayout,
+) -> Result<(char, GlyphMetrics), String> {
+    let x: u32 = field(fields, "x")?;
+    let y: u32 = field(fields, "y")?;
+    let width: u32 = field(fields, "width")?;
+    let height: u32 = field(fields, "height")?;
+    let metrics = GlyphMetrics {
+        u_min: x as f32 / layout.width as f32,
+        v_min: y as f32 / layout.height as f32,
+        u_max: (x + width) as f32 / layout.width as f32,
+        v_max: (y + height) as f32 / layout.height as f32,
+        layer: field(fields, "layer")?,
+        width,
+        height,
+        bearing_x: field(fields, "bearing_x")?,
+        bearing_y: field(fields, "bearing_y")?,
+        advance: field(fields, "advance")?,
+    };
+    Ok((char_field(fields)?, metrics))
+}
+
+pub fn parse_metrics(text: &str) -> Result<AtlasMetrics, String> {
+    let mut atlas = None;
+    let mut pages = Vec::new();
+    let mut records = Vec::new();
+
+    for (line_number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (tag, fields) = parse_record(line);
+        let result = match tag {
+            "atlas" => layout_fields(&fields).map(|layout| atlas = Some(layout)),
+            "page" => field::<String>(&fields, "file").map(|file| pages.push(file)),
+            "glyph" => {
+                records.push((line_number, fields));
+                Ok(())
+            }
+            _ => Err(format!("Unknown record {:?}", tag)),
+        };
+        result.map_err(|e| format!("line {}: {}", line_number + 1, e))?;
+    }
+
+    let (layout, ascent) = atlas.ok_or("Missing atlas record")?;
+    let mut glyph_map = HashMap::new();
+    for (line_number, fields) in records {
+        let (ch, metrics) = glyph_fields(&fields, &layout)
+            .map_err(|e| format!("line {}: {}", line_number + 1, e))?;
+        glyph_map.insert(ch, metrics);
+    }
+
+    Ok(AtlasMetrics {
+        layout,
+        ascent,
+        pages,
+        glyph_map,
+    })
+}
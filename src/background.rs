@@ -0,0 +1,300 @@
+use bytemuck::{Pod, Zeroable};
+use image::RgbaImage;
+use std::time::{Duration, Instant};
+
+use crate::config::BackgroundConfig;
+use crate::renderer::write_atlas_image;
+use crate::y4m::Y4mReader;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct BackgroundUniforms {
+    uv_scale: [f32; 2],
+    uv_offset: [f32; 2],
+    blur: [f32; 2],
+    dim: f32,
+    _padding: f32,
+}
+
+enum Source {
+    Image,
+    Video {
+        video: Y4mReader,
+        // When the frame after the one on screen is due
+        next_frame: Instant,
+    },
+}
+
+// Image or looping video drawn over the whole screen before the rain
+pub struct Background {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    texture: wgpu::Texture,
+    uniforms_buffer: wgpu::Buffer,
+    source: Source,
+    pan: [f32; 2],
+    dim: f32,
+    blur: f32,
+    started: Instant,
+}
+
+impl Background {
+    // The configured background, or None when there is none or it fails to load
+    pub fn load(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &BackgroundConfig,
+        format: wgpu::TextureFormat,
+    ) -> Option<Self> {
+        let max_size = device.limits().max_texture_dimension_2d;
+        let loaded = if let Some(path) = &config.video {
+            Y4mReader::open(path, max_size).and_then(|mut video| {
+                let frame = video.next_frame()?;
+                eprintln!(
+                    "[Background] Playing {} ({}x{})",
+                    path.display(),
+                    video.width(),
+                    video.height()
+                );
+                let next_frame = Instant::now() + video.frame_time();
+                Ok((frame, Source::Video { video, next_frame }))
+            })
+        } else if let Some(path) = &config.image {
+            image::open(path)
+                .map(|image| (fit_texture(image.to_rgba8(), max_size), Source::Image))
+                .map_err(|e| format!("Failed to load {}: {}", path.display(), e))
+        } else {
+            return None;
+        };
+
+        match loaded {
+            Ok((frame, source)) => Some(Self::new(device, queue, config, format, frame, source)),
+            Err(e) => {
+                eprintln!("[Background] {}, drawing the theme color instead", e);
+                None
+            }
+        }
+    }
+
+    fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &BackgroundConfig,
+        format: wgpu::TextureFormat,
+        frame: RgbaImage,
+        source: Source,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Background Shader"),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!(
+                "../shaders/background.wgsl"
+            ))),
+        });
+
+        // Images and video frames hold sRGB-encoded colors
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Background Texture"),
+            size: wgpu::Extent3d {
+                width: frame.width(),
+                height: frame.height(),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        write_atlas_image(queue, &texture, 0, 0, 0, 0, &frame);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // Panning past the edge continues into a mirrored copy, without a seam
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Background Sampler"),
+            address_mode_u: wgpu::AddressMode::MirrorRepeat,
+            address_mode_v: wgpu::AddressMode::MirrorRepeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let uniforms_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Background Uniforms Buffer"),
+            size: std::mem::size_of::<BackgroundUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Background Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Background Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniforms_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Background Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        // Opaque, so it replaces the clear color everywhere
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Background Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+
+        Self {
+            pipeline,
+            bind_group,
+            texture,
+            uniforms_buffer,
+            source,
+            pan: config.pan,
+            dim: config.dim.clamp(0.0, 1.0),
+            blur: config.blur.max(0.0),
+            started: Instant::now(),
+        }
+    }
+
+    // Show the video frame that is due and place the image on a screen of
+    // `width` x `height` pixels
+    pub fn update(&mut self, queue: &wgpu::Queue, width: u32, height: u32) {
+        let now = Instant::now();
+        if let Source::Video { video, next_frame } = &mut self.source {
+            if now >= *next_frame {
+                match video.next_frame() {
+                    Ok(frame) => write_atlas_image(queue, &self.texture, 0, 0, 0, 0, &frame),
+                    Err(e) => eprintln!("[Background] {}", e),
+                }
+                // Decoding one frame per render, a slow decoder drops behind
+                // instead of trying to catch up
+                *next_frame = (*next_frame + video.frame_time()).max(now);
+            }
+        }
+
+        let uniforms = self.uniforms(now.duration_since(self.started), width, height);
+        queue.write_buffer(&self.uniforms_buffer, 0, bytemuck::bytes_of(&uniforms));
+    }
+
+    fn uniforms(&self, elapsed: Duration, width: u32, height: u32) -> BackgroundUniforms {
+        let screen = [width.max(1) as f32, height.max(1) as f32];
+        let size = self.texture.size();
+        let image = [size.width as f32, size.height as f32];
+
+        // Cover the screen, cropping whichever side of the image is too long
+        let screen_aspect = screen[0] / screen[1];
+        let image_aspect = image[0] / image[1];
+        let uv_scale = [
+            (screen_aspect / image_aspect).min(1.0),
+            (image_aspect / screen_aspect).min(1.0),
+        ];
+
+        let seconds = elapsed.as_secs_f32();
+        let uv_offset = [
+            self.pan[0] * seconds * uv_scale[0],
+            self.pan[1] * seconds * uv_scale[1],
+        ];
+        // The blur radius is in screen pixels
+        let blur = [
+            self.blur / screen[0] * uv_scale[0],
+            self.blur / screen[1] * uv_scale[1],
+        ];
+
+        BackgroundUniforms {
+            uv_scale,
+            // Keep the offset small, where f32 texture coordinates are precise
+            uv_offset: uv_offset.map(|offset| offset % 2.0),
+            blur,
+            dim: self.dim,
+            _padding: 0.0,
+        }
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+// Shrink an image that is too large for a texture, keeping its aspect ratio
+fn fit_texture(image: RgbaImage, max_size: u32) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    if width <= max_size && height <= max_size {
+        return image;
+    }
+    let scale = max_size as f32 / width.max(height) as f32;
+    let fitted = (
+        ((width as f32 * scale) as u32).clamp(1, max_size),
+        ((height as f32 * scale) as u32).clamp(1, max_size),
+    );
+    eprintln!(
+        "[Background] Scaling the {}x{} image down to {}x{}, the texture size limit",
+        width, height, fitted.0, fitted.1
+    );
+    image::imageops::resize(&image, fitted.0, fitted.1, image::imageops::FilterType::Triangle)
+}
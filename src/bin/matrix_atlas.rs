@@ -0,0 +1,271 @@
+// matrix-atlas: build glyph atlases and inspect font coverage without rebuilding the app.
+//
+//   matrix-atlas build <font> [options]     Write <out>-N.png pages and <out>.atlas metrics
+//   matrix-atlas coverage <font> [options]  Report which requested characters the fonts cover
+//
+// Options:
+//   --charsets katakana,digits     Built-in charsets (see src/charsets.rs)
+//   --ranges 0021-007E,FF66-FF9D   Unicode code points
+//   --chars "ABC"                  Literal characters, added to --charsets and --ranges
+//                                  (default: the characters of the embedded atlas)
+//   --fallback <font>              Fallback font, may be repeated
+//   --glyph-size <px>              Glyph cell size (default 32)
+//   --padding <px>                 Padding between cells (default 8)
+//   --atlas-size <px>              Page width and height (default 2048)
+//   --out <path>                   Output path without extension (default "atlas")
+//   --format <atlas|fnt|fnt-xml|fnt-binary>
+//                                  Metrics format: matrix-atlas, or BMFont text, XML or
+//                                  binary (default atlas)
+//
+// Either metrics file can be loaded by the app through `font.atlas` in matrix.toml.
+
+use ab_glyph::FontVec;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+#[path = "../atlas_builder.rs"]
+#[allow(dead_code)]
+mod atlas_builder;
+#[path = "../bmfont.rs"]
+#[allow(dead_code)]
+mod bmfont;
+#[path = "../charsets.rs"]
+#[allow(dead_code)]
+mod charsets;
+
+// Characters of the embedded atlas, without its pages and font
+mod embedded {
+    include!(concat!(env!("OUT_DIR"), "/charset.rs"));
+}
+
+use atlas_builder::{AtlasLayout, AtlasMetrics};
+
+struct Options {
+    fonts: Vec<PathBuf>,
+    charset: Vec<char>,
+    layout: AtlasLayout,
+    out: PathBuf,
+    format: Format,
+}
+
+#[derive(Copy, Clone)]
+enum Format {
+    Atlas,
+    BmFont,
+    BmFontXml,
+    BmFontBinary,
+}
+
+fn usage() -> ExitCode {
+    eprintln!("Usage: matrix-atlas <build|coverage> <font> [--charsets S] [--ranges R] [--chars C]");
+    eprintln!("                    [--fallback F]");
+    eprintln!("                    [--glyph-size N] [--padding N] [--atlas-size N] [--out PATH]");
+    eprintln!("                    [--format atlas|fnt|fnt-xml|fnt-binary]");
+    ExitCode::FAILURE
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut fonts = Vec::new();
+    let mut sets = String::new();
+    let mut ranges = String::new();
+    let mut chars = String::new();
+    let mut layout = AtlasLayout::new(32);
+    let mut out = PathBuf::from("atlas");
+    let mut format = Format::Atlas;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("Missing value for {}", arg));
+        let number = |text: &String| {
+            text.parse::<u32>()
+                .map_err(|_| format!("Invalid number {:?} for {}", text, arg))
+        };
+        match arg.as_str() {
+            "--charsets" => sets = value()?.clone(),
+            "--ranges" => ranges = value()?.clone(),
+            "--chars" => chars.push_str(value()?),
+            "--fallback" => fonts.push(PathBuf::from(value()?)),
+            "--glyph-size" => layout.glyph_size = number(value()?)?,
+            "--padding" => layout.padding = number(value()?)?,
+            "--atlas-size" => {
+                let size = number(value()?)?;
+                layout.width = size;
+                layout.height = size;
+            }
+            "--out" => out = PathBuf::from(value()?),
+            "--format" => {
+                format = match value()?.as_str() {
+                    "atlas" => Format::Atlas,
+                    "fnt" => Format::BmFont,
+                    "fnt-xml" => Format::BmFontXml,
+                    "fnt-binary" => Format::BmFontBinary,
+                    other => return Err(format!("Unknown format {:?}", other)),
+                }
+            }
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+            // The primary font goes first in the fallback chain
+            font => fonts.insert(0, PathBuf::from(font)),
+        }
+    }
+
+    if fonts.is_empty() {
+        return Err("No font given".to_string());
+    }
+    layout.validate()?;
+
+    let list = |text: &str| -> Vec<String> {
+        let items = text.split(',').filter(|item| !item.is_empty());
+        items.map(str::to_owned).collect()
+    };
+    // Without any characters requested, build what the app embeds
+    let charset = if sets.is_empty() && chars.is_empty() && ranges.is_empty() {
+        embedded::CHARSET.chars().collect()
+    } else {
+        charsets::combine(&list(&sets), &chars, &list(&ranges))?
+    };
+
+    Ok(Options {
+        fonts,
+        charset,
+        layout,
+        out,
+        format,
+    })
+}
+
+fn load_fonts(paths: &[PathBuf]) -> Result<Vec<FontVec>, String> {
+    paths
+        .iter()
+        .map(|path| {
+            let data = std::fs::read(path)
+                .map_err(|e| format!("Failed to read font {}: {}", path.display(), e))?;
+            FontVec::try_from_vec(data)
+                .map_err(|e| format!("Failed to parse font {}: {}", path.display(), e))
+        })
+        .collect()
+}
+
+fn build(options: &Options) -> Result<(), String> {
+    let fonts = load_fonts(&options.fonts)?;
+    let atlas = atlas_builder::rasterize_atlas(&fonts, &options.charset, &options.layout)?;
+
+    // Page files are stored relative to the metrics file
+    let stem = options
+        .out
+        .file_name()
+        .ok_or("Invalid output path")?
+        .to_string_lossy()
+        .into_owned();
+    let dir = options.out.parent().unwrap_or(Path::new(""));
+    if !dir.as_os_str().is_empty() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+
+    let mut page_files = Vec::new();
+    for (id, page) in atlas.pages.iter().enumerate() {
+        let file = format!("{}-{}.png", stem, id);
+        page.save(dir.join(&file))
+            .map_err(|e| format!("Failed to write {}: {}", file, e))?;
+        page_files.push(file);
+    }
+
+    let metrics = AtlasMetrics {
+        layout: options.layout,
+        ascent: atlas.ascent,
+        pages: page_files,
+        glyph_map: atlas.glyph_map,
+    };
+    let face = options.fonts[0].file_stem().unwrap_or_default().to_string_lossy();
+    let contents = match options.format {
+        Format::Atlas => atlas_builder::write_metrics(&metrics).into_bytes(),
+        Format::BmFont => bmfont::write_text(&metrics, &face).into_bytes(),
+        Format::BmFontXml => bmfont::write_xml(&metrics, &face).into_bytes(),
+        Format::BmFontBinary => bmfont::write_binary(&metrics, &face),
+    };
+    let extension = match options.format {
+        Format::Atlas => "atlas",
+        _ => "fnt",
+    };
+    let metrics_path = options.out.with_extension(extension);
+    std::fs::write(&metrics_path, contents)
+        .map_err(|e| format!("Failed to write {}: {}", metrics_path.display(), e))?;
+
+    println!(
+        "Wrote {} glyphs on {} page(s) to {}",
+        metrics.glyph_map.len(),
+        metrics.pages.len(),
+        metrics_path.display()
+    );
+    if !atlas.failed_chars.is_empty() {
+        println!("{} characters could not be rasterized:", atlas.failed_chars.len());
+        for ch in &atlas.failed_chars {
+            println!("  - {:?} (U+{:04X})", ch, *ch as u32);
+        }
+    }
+    Ok(())
+}
+
+fn coverage(options: &Options) -> Result<(), String> {
+    let fonts = load_fonts(&options.fonts)?;
+
+    let mut missing = Vec::new();
+    let mut per_font = vec![0usize; fonts.len()];
+    for &ch in &options.charset {
+        // Attribute each character to the first font that has it, like the atlas does
+        match (0..fonts.len()).find(|&i| atlas_builder::is_covered(&fonts[i..=i], ch)) {
+            Some(i) => per_font[i] += 1,
+            None => missing.push(ch),
+        }
+    }
+
+    let total = options.charset.len();
+    println!(
+        "{} of {} characters covered ({:.1}%)",
+        total - missing.len(),
+        total,
+        (total - missing.len()) as f32 / total.max(1) as f32 * 100.0
+    );
+    for (path, count) in options.fonts.iter().zip(&per_font) {
+        println!("  {:>6}  {}", count, path.display());
+    }
+    if !missing.is_empty() {
+        println!("Missing:");
+        for ch in &missing {
+            println!("  - {:?} (U+{:04X})", ch, *ch as u32);
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some((command, rest)) = args.split_first() else {
+        return usage();
+    };
+
+    let options = match parse_options(rest) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("matrix-atlas: {}", e);
+            return usage();
+        }
+    };
+
+    let result = match command.as_str() {
+        "build" => build(&options),
+        "coverage" => coverage(&options),
+        _ => {
+            eprintln!("matrix-atlas: Unknown command {:?}", command);
+            return usage();
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("matrix-atlas: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,566 @@
+// AngelCode BMFont (.fnt) support: text, XML and binary descriptors for atlases
+// authored in tools such as Hiero or msdf-atlas-gen. Shared with matrix-atlas,
+// which exports all three flavours.
+
+use std::collections::HashMap;
+
+use crate::atlas_builder::{self, field, AtlasLayout, AtlasMetrics, GlyphMetrics};
+
+// Block ids of the binary format (version 3)
+const BLOCK_INFO: u8 = 1;
+const BLOCK_COMMON: u8 = 2;
+const BLOCK_PAGES: u8 = 3;
+const BLOCK_CHARS: u8 = 4;
+const BINARY_CHAR_SIZE: usize = 20;
+// Offset of the font name in the binary info block
+const BINARY_INFO_SIZE: usize = 14;
+
+// The fields of a .fnt we use, independent of the flavour it was stored in
+#[derive(Default)]
+struct Descriptor {
+    spacing: u32,
+    line_height: u32,
+    base: u32,
+    scale_w: u32,
+    scale_h: u32,
+    pages: Vec<String>,
+    chars: Vec<CharRecord>,
+}
+
+struct CharRecord {
+    id: u32,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    x_offset: f32,
+    y_offset: f32,
+    x_advance: f32,
+    page: u32,
+}
+
+// Parse a BMFont descriptor in any of its three flavours
+pub fn parse(data: &[u8]) -> Result<AtlasMetrics, String> {
+    let descriptor = if data.starts_with(b"BMF") {
+        parse_binary(data)?
+    } else {
+        let text = std::str::from_utf8(data).map_err(|_| "BMFont file is not valid UTF-8")?;
+        if text.trim_start().starts_with('<') {
+            parse_records(xml_elements(text), decode_entities)?
+        } else {
+            parse_records(text.lines().map(str::trim), str::to_owned)?
+        }
+    };
+    descriptor.into_metrics()
+}
+
+// Serialize atlas metrics as a text-format BMFont descriptor
+pub fn write_text(metrics: &AtlasMetrics, face: &str) -> String {
+    let descriptor = Descriptor::from_metrics(metrics);
+    let mut output = String::new();
+
+    output.push_str(&format!(
+        "info face=\"{}\" size={} bold=0 italic=0 charset=\"\" unicode=1 stretchH=100 smooth=1 \
+         aa=1 padding=0,0,0,0 spacing={},{}\n",
+        face, descriptor.line_height, descriptor.spacing, descriptor.spacing
+    ));
+    output.push_str(&format!(
+        "common lineHeight={} base={} scaleW={} scaleH={} pages={} packed=0\n",
+        descriptor.line_height,
+        descriptor.base,
+        descriptor.scale_w,
+        descriptor.scale_h,
+        descriptor.pages.len()
+    ));
+    for (id, file) in descriptor.pages.iter().enumerate() {
+        output.push_str(&format!("page id={} file=\"{}\"\n", id, file));
+    }
+
+    output.push_str(&format!("chars count={}\n", descriptor.chars.len()));
+    for c in &descriptor.chars {
+        output.push_str(&format!(
+            "char id={} x={} y={} width={} height={} xoffset={} yoffset={} xadvance={} \
+             page={} chnl=15\n",
+            c.id, c.x, c.y, c.width, c.height, c.x_offset, c.y_offset, c.x_advance, c.page
+        ));
+    }
+    output
+}
+
+// Serialize atlas metrics as an XML-format BMFont descriptor
+pub fn write_xml(metrics: &AtlasMetrics, face: &str) -> String {
+    let descriptor = Descriptor::from_metrics(metrics);
+    let mut output = String::from("<?xml version=\"1.0\"?>\n<font>\n");
+
+    output.push_str(&format!(
+        "  <info face=\"{}\" size=\"{}\" bold=\"0\" italic=\"0\" charset=\"\" unicode=\"1\" \
+         stretchH=\"100\" smooth=\"1\" aa=\"1\" padding=\"0,0,0,0\" spacing=\"{},{}\"/>\n",
+        encode_entities(face),
+        descriptor.line_height,
+        descriptor.spacing,
+        descriptor.spacing
+    ));
+    output.push_str(&format!(
+        "  <common lineHeight=\"{}\" base=\"{}\" scaleW=\"{}\" scaleH=\"{}\" pages=\"{}\" \
+         packed=\"0\"/>\n",
+        descriptor.line_height,
+        descriptor.base,
+        descriptor.scale_w,
+        descriptor.scale_h,
+        descriptor.pages.len()
+    ));
+    output.push_str("  <pages>\n");
+    for (id, file) in descriptor.pages.iter().enumerate() {
+        let file = encode_entities(file);
+        output.push_str(&format!("    <page id=\"{}\" file=\"{}\"/>\n", id, file));
+    }
+    output.push_str("  </pages>\n");
+
+    output.push_str(&format!("  <chars count=\"{}\">\n", descriptor.chars.len()));
+    for c in &descriptor.chars {
+        output.push_str(&format!(
+            "    <char id=\"{}\" x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" xoffset=\"{}\" \
+             yoffset=\"{}\" xadvance=\"{}\" page=\"{}\" chnl=\"15\"/>\n",
+            c.id, c.x, c.y, c.width, c.height, c.x_offset, c.y_offset, c.x_advance, c.page
+        ));
+    }
+    output.push_str("  </chars>\n</font>\n");
+    output
+}
+
+// Serialize atlas metrics as a binary (version 3) BMFont descriptor. Sizes and
+// positions are stored in 16 bits and the spacing in 8.
+pub fn write_binary(metrics: &AtlasMetrics, face: &str) -> Vec<u8> {
+    let descriptor = Descriptor::from_metrics(metrics);
+    let mut output = b"BMF\x03".to_vec();
+    let mut block = |block_type: u8, contents: Vec<u8>| {
+        output.push(block_type);
+        output.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+        output.extend_from_slice(&contents);
+    };
+    let u16_bytes = |value: u32| (value.min(u16::MAX as u32) as u16).to_le_bytes();
+    let i16_bytes = |value: f32| (value as i16).to_le_bytes();
+
+    // Size, smooth and unicode flags, stretchH, aa, padding, spacing, outline, name
+    let mut info = Vec::with_capacity(BINARY_INFO_SIZE + face.len() + 1);
+    info.extend_from_slice(&(descriptor.line_height as i16).to_le_bytes());
+    info.extend_from_slice(&[0b1100_0000, 0]);
+    info.extend_from_slice(&100u16.to_le_bytes());
+    info.extend_from_slice(&[1, 0, 0, 0, 0]);
+    let spacing = descriptor.spacing.min(u8::MAX as u32) as u8;
+    info.extend_from_slice(&[spacing, spacing, 0]);
+    info.extend_from_slice(face.as_bytes());
+    info.push(0);
+    block(BLOCK_INFO, info);
+
+    // Line height, base, page size, page count, packing and channel contents
+    let mut common = Vec::new();
+    for value in [
+        descriptor.line_height,
+        descriptor.base,
+        descriptor.scale_w,
+        descriptor.scale_h,
+        descriptor.pages.len() as u32,
+    ] {
+        common.extend_from_slice(&u16_bytes(value));
+    }
+    common.extend_from_slice(&[0, 0, 0, 0, 0]);
+    block(BLOCK_COMMON, common);
+
+    let mut pages = Vec::new();
+    for file in &descriptor.pages {
+        pages.extend_from_slice(file.as_bytes());
+        pages.push(0);
+    }
+    block(BLOCK_PAGES, pages);
+
+    let mut chars = Vec::with_capacity(descriptor.chars.len() * BINARY_CHAR_SIZE);
+    for c in &descriptor.chars {
+        chars.extend_from_slice(&c.id.to_le_bytes());
+        for value in [c.x, c.y, c.width, c.height] {
+            chars.extend_from_slice(&u16_bytes(value));
+        }
+        for value in [c.x_offset, c.y_offset, c.x_advance] {
+            chars.extend_from_slice(&i16_bytes(value));
+        }
+        chars.extend_from_slice(&[c.page.min(u8::MAX as u32) as u8, 15]);
+    }
+    block(BLOCK_CHARS, chars);
+
+    output
+}
+
+impl Descriptor {
+    // Glyph offsets and advances are rounded to whole texels, as BMFont stores them
+    fn from_metrics(metrics: &AtlasMetrics) -> Self {
+        let layout = &metrics.layout;
+        let base = metrics.ascent.round();
+
+        let mut glyphs: Vec<_> = metrics.glyph_map.iter().collect();
+        glyphs.sort_by_key(|(ch, _)| **ch);
+        let chars = glyphs
+            .into_iter()
+            .map(|(ch, m)| CharRecord {
+                id: *ch as u32,
+                x: (m.u_min * layout.width as f32).round() as u32,
+                y: (m.v_min * layout.height as f32).round() as u32,
+                width: m.width,
+                height: m.height,
+                x_offset: m.bearing_x.round(),
+                y_offset: (base + m.bearing_y).round(),
+                x_advance: m.advance.round(),
+                page: m.layer,
+            })
+            .collect();
+
+        Self {
+            spacing: layout.padding,
+            line_height: layout.glyph_size,
+            base: base as u32,
+            scale_w: layout.width,
+            scale_h: layout.height,
+            pages: metrics.pages.clone(),
+            chars,
+        }
+    }
+
+    fn into_metrics(self) -> Result<AtlasMetrics, String> {
+        let layout = AtlasLayout {
+            width: self.scale_w,
+            height: self.scale_h,
+            glyph_size: self.line_height,
+            padding: self.spacing,
+        };
+
+        let mut glyph_map: HashMap<char, GlyphMetrics> = HashMap::new();
+        for c in &self.chars {
+            let Some(ch) = char::from_u32(c.id) else {
+                continue;
+            };
+            let right = c.x.checked_add(c.width).filter(|&end| end <= layout.width);
+            let bottom = c.y.checked_add(c.height).filter(|&end| end <= layout.height);
+            let (Some(right), Some(bottom)) = (right, bottom) else {
+                return Err(format!(
+                    "Glyph {} at {},{} ({}x{}) lies outside the {}x{} page",
+                    c.id, c.x, c.y, c.width, c.height, layout.width, layout.height
+                ));
+            };
+            let metrics = GlyphMetrics {
+                u_min: c.x as f32 / layout.width as f32,
+                v_min: c.y as f32 / layout.height as f32,
+                u_max: right as f32 / layout.width as f32,
+                v_max: bottom as f32 / layout.height as f32,
+                layer: c.page,
+                width: c.width,
+                height: c.height,
+                // BMFont offsets are from the top of the line; ours are from the baseline
+                bearing_x: c.x_offset,
+                bearing_y: c.y_offset - self.base as f32,
+                advance: c.x_advance,
+            };
+            glyph_map.insert(ch, metrics);
+        }
+
+        Ok(AtlasMetrics {
+            layout,
+            ascent: self.base as f32,
+            pages: self.pages,
+            glyph_map,
+        })
+    }
+}
+
+// Text and XML flavours share tag names and attributes, so both reduce to
+// "tag key=value ..." records; `unescape` decodes the page file names
+fn parse_records<'a>(
+    records: impl Iterator<Item = &'a str>,
+    unescape: fn(&str) -> String,
+) -> Result<Descriptor, String> {
+    let mut descriptor = Descriptor::default();
+    let mut pages = Vec::new();
+
+    for record in records {
+        let (tag, fields) = atlas_builder::parse_record(record);
+        match tag {
+            "info" => {
+                // "spacing=h,v"; cells are square, so the horizontal spacing is enough
+                let spacing = fields.get("spacing").and_then(|s| s.split(',').next());
+                descriptor.spacing = spacing.and_then(|s| s.parse().ok()).unwrap_or(0);
+            }
+            "common" => {
+                descriptor.line_height = field(&fields, "lineHeight")?;
+                descriptor.base = field(&fields, "base")?;
+                descriptor.scale_w = field(&fields, "scaleW")?;
+                descriptor.scale_h = field(&fields, "scaleH")?;
+            }
+            "page" => {
+                let file = unescape(&field::<String>(&fields, "file")?);
+                pages.push((field::<u32>(&fields, "id")?, file));
+            }
+            "char" => descriptor.chars.push(CharRecord {
+                id: field(&fields, "id")?,
+                x: field(&fields, "x")?,
+                y: field(&fields, "y")?,
+                width: field(&fields, "width")?,
+                height: field(&fields, "height")?,
+                x_offset: field(&fields, "xoffset")?,
+                y_offset: field(&fields, "yoffset")?,
+                x_advance: field(&fields, "xadvance")?,
+                page: field(&fields, "page")?,
+            }),
+            // chars, kernings and anything else carry nothing we need
+            _ => {}
+        }
+    }
+
+    if descriptor.scale_w == 0 || descriptor.scale_h == 0 {
+        return Err("Missing common block".to_string());
+    }
+    pages.sort_by_key(|(id, _)| *id);
+    descriptor.pages = pages.into_iter().map(|(_, file)| file).collect();
+    Ok(descriptor)
+}
+
+// Yield the contents of each XML element tag ("char id=\"65\" ..."), skipping
+// the prolog, comments and closing tags
+fn xml_elements(text: &str) -> impl Iterator<Item = &str> {
+    text.split('<').filter_map(|chunk| {
+        let (element, _) = chunk.split_once('>')?;
+        if element.starts_with('?') || element.starts_with('!') || element.starts_with('/') {
+            return None;
+        }
+        Some(element.trim_end_matches('/').trim())
+    })
+}
+
+// Replace XML's predefined entities and character references with the
+// characters they stand for; anything unrecognised is kept as written
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest[1..].split_once(';').and_then(|(name, _)| {
+            let ch = match name {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                _ => {
+                    let code = match name.strip_prefix("#x").or(name.strip_prefix("#X")) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => name.strip_prefix('#')?.parse().ok()?,
+                    };
+                    char::from_u32(code)?
+                }
+            };
+            Some((ch, name.len() + 2))
+        });
+        match entity {
+            Some((ch, length)) => {
+                decoded.push(ch);
+                rest = &rest[length..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn encode_entities(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => encoded.push_str("&amp;"),
+            '<' => encoded.push_str("&lt;"),
+            '>' => encoded.push_str("&gt;"),
+            '"' => encoded.push_str("&quot;"),
+            '\'' => encoded.push_str("&apos;"),
+            _ => encoded.push(ch),
+        }
+    }
+    encoded
+}
+
+fn parse_binary(data: &[u8]) -> Result<Descriptor, String> {
+    if data.len() < 4 || data[3] != 3 {
+        return Err("Unsupported BMFont binary version".to_string());
+    }
+
+    let u16_at =
+        |block: &[u8], offset: usize| u16::from_le_bytes([block[offset], block[offset + 1]]);
+    let i16_at =
+        |block: &[u8], offset: usize| i16::from_le_bytes([block[offset], block[offset + 1]]);
+
+    let mut descriptor = Descriptor::default();
+    let mut rest = &data[4..];
+    while rest.len() >= 5 {
+        let block_type = rest[0];
+        let size = u32::from_le_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+        let block = rest
+            .get(5..5 + size)
+            .ok_or("Truncated BMFont binary block")?;
+        rest = &rest[5 + size..];
+
+        match block_type {
+            BLOCK_INFO if size >= 12 => descriptor.spacing = block[11] as u32,
+            BLOCK_COMMON if size >= 8 => {
+                descriptor.line_height = u16_at(block, 0) as u32;
+                descriptor.base = u16_at(block, 2) as u32;
+                descriptor.scale_w = u16_at(block, 4) as u32;
+                descriptor.scale_h = u16_at(block, 6) as u32;
+            }
+            BLOCK_PAGES => {
+                descriptor.pages = block
+                    .split(|&byte| byte == 0)
+                    .filter(|name| !name.is_empty())
+                    .map(|name| String::from_utf8_lossy(name).into_owned())
+                    .collect();
+            }
+            BLOCK_CHARS => {
+                for record in block.chunks_exact(BINARY_CHAR_SIZE) {
+                    descriptor.chars.push(CharRecord {
+                        id: u32::from_le_bytes([record[0], record[1], record[2], record[3]]),
+                        x: u16_at(record, 4) as u32,
+                        y: u16_at(record, 6) as u32,
+                        width: u16_at(record, 8) as u32,
+                        height: u16_at(record, 10) as u32,
+                        x_offset: i16_at(record, 12) as f32,
+                        y_offset: i16_at(record, 14) as f32,
+                        x_advance: i16_at(record, 16) as f32,
+                        page: record[18] as u32,
+                    });
+                }
+            }
+            // Kerning blocks carry nothing we need
+            _ => {}
+        }
+    }
+
+    if descriptor.scale_w == 0 || descriptor.scale_h == 0 {
+        return Err("Missing common block".to_string());
+    }
+    Ok(descriptor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Metrics of the bundled font's printable ASCII, as `matrix-atlas build`
+    // produces them
+    fn built_metrics(page: &str) -> AtlasMetrics {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/font/matrix code nfi.ttf");
+        let font = ab_glyph::FontVec::try_from_vec(std::fs::read(path).unwrap()).unwrap();
+        // The font has no '@' or '`'
+        let charset: Vec<char> = ('!'..='~').filter(|ch| !"@`".contains(*ch)).collect();
+        let layout = AtlasLayout::new(32);
+        let atlas = atlas_builder::rasterize_atlas(&[font], &charset, &layout).unwrap();
+        AtlasMetrics {
+            layout,
+            ascent: atlas.ascent,
+            pages: vec![page.to_string()],
+            glyph_map: atlas.glyph_map,
+        }
+    }
+
+    // BMFont rounds offsets and advances to whole texels, so they may move by
+    // the rounding of both the glyph and the baseline
+    fn assert_round_trip(parsed: &AtlasMetrics, original: &AtlasMetrics) {
+        assert_eq!(parsed.layout, original.layout);
+        assert_eq!(parsed.ascent, original.ascent.round());
+        assert_eq!(parsed.pages, original.pages);
+        assert_eq!(parsed.glyph_map.len(), original.glyph_map.len());
+        for (ch, glyph) in &original.glyph_map {
+            let parsed = parsed.glyph_map[ch];
+            assert_eq!(
+                (parsed.u_min, parsed.v_min, parsed.u_max, parsed.v_max),
+                (glyph.u_min, glyph.v_min, glyph.u_max, glyph.v_max),
+                "{:?}",
+                ch
+            );
+            assert_eq!(parsed.layer, glyph.layer);
+            assert_eq!((parsed.width, parsed.height), (glyph.width, glyph.height));
+            assert!(
+                (parsed.bearing_x - glyph.bearing_x).abs() <= 0.5,
+                "{:?}",
+                ch
+            );
+            assert!(
+                (parsed.bearing_y - glyph.bearing_y).abs() <= 1.0,
+                "{:?}",
+                ch
+            );
+            assert!((parsed.advance - glyph.advance).abs() <= 0.5, "{:?}", ch);
+        }
+    }
+
+    #[test]
+    fn text_round_trip() {
+        // The text flavour has no escapes, so names can't contain quotes
+        let metrics = built_metrics("R&D <0>.png");
+        let text = write_text(&metrics, "matrix code nfi");
+        assert_round_trip(&parse(text.as_bytes()).unwrap(), &metrics);
+    }
+
+    #[test]
+    fn xml_round_trip() {
+        let metrics = built_metrics("R&D \"matrix\" <0>.png");
+        let xml = write_xml(&metrics, "R&D");
+        assert!(xml.contains("file=\"R&amp;D &quot;matrix&quot; &lt;0&gt;.png\""));
+        assert_round_trip(&parse(xml.as_bytes()).unwrap(), &metrics);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let metrics = built_metrics("matrix-0.png");
+        let binary = write_binary(&metrics, "matrix code nfi");
+        assert_round_trip(&parse(&binary).unwrap(), &metrics);
+    }
+
+    #[test]
+    fn decodes_xml_entities() {
+        let xml = "<?xml version=\"1.0\"?>\n<font>\n\
+                   <common lineHeight=\"32\" base=\"26\" scaleW=\"256\" scaleH=\"256\"/>\n\
+                   <pages><page id=\"0\" file=\"a&amp;b&#45;&#x30;.png\"/></pages>\n\
+                   </font>\n";
+        assert_eq!(parse(xml.as_bytes()).unwrap().pages, ["a&b-0.png"]);
+        assert_eq!(decode_entities("&lt;&gt;&quot;&apos;"), "<>\"'");
+        // Unknown or unterminated entities are kept as written
+        assert_eq!(
+            decode_entities("&nbsp; & &#xZZ; &amp"),
+            "&nbsp; & &#xZZ; &amp"
+        );
+    }
+
+    #[test]
+    fn rejects_descriptors_without_common_block() {
+        assert!(parse(b"info face=\"x\" size=32\npage id=0 file=\"a.png\"\n").is_err());
+        assert!(parse(b"<font><pages><page id=\"0\" file=\"a.png\"/></pages></font>").is_err());
+        assert!(parse(b"BMF\x03").is_err());
+        assert!(parse(b"BMF\x02").is_err());
+        assert!(parse(b"BMF\x03\x02\x10\x00\x00\x00\x20").is_err());
+    }
+    #[test]
+    fn rejects_glyphs_outside_the_page() {
+        let fnt = |rect: &str| {
+            format!(
+                "common lineHeight=32 base=26 scaleW=256 scaleH=256 pages=1\n\
+                 page id=0 file=\"a.png\"\n\
+                 char id=65 {} xoffset=0 yoffset=0 xadvance=20 page=0\n",
+                rect
+            )
+        };
+        assert!(parse(fnt("x=224 y=224 width=32 height=32").as_bytes()).is_ok());
+        assert!(parse(fnt("x=240 y=0 width=32 height=32").as_bytes()).is_err());
+        assert!(parse(fnt("x=0 y=240 width=32 height=32").as_bytes()).is_err());
+        // x + width overflows u32
+        assert!(parse(fnt("x=4294967295 y=0 width=2 height=32").as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,90 @@
+// Built-in named charsets. Shared with build.rs and matrix-atlas through #[path],
+// so it may only depend on atlas_builder and std.
+
+use crate::atlas_builder;
+
+// Name and Unicode ranges of each built-in charset
+const NAMED_CHARSETS: &[(&str, &[&str])] = &[
+    // Half-width katakana, as in the film
+    ("katakana", &["FF66-FF9D"]),
+    ("ascii", &["0021-007E"]),
+    ("digits", &["0030-0039"]),
+    ("binary", &["0030-0031"]),
+    ("hex", &["0030-0039", "0041-0046"]),
+    ("greek", &["0391-03A1", "03A3-03A9", "03B1-03C9"]),
+    ("cyrillic", &["0410-044F"]),
+    // Compatibility jamo: the consonant and vowel letters
+    ("hangul", &["3131-3163"]),
+    ("braille", &["2801-28FF"]),
+    ("runic", &["16A0-16EA"]),
+];
+
+// Sets drawn with every glyph flipped horizontally (see atlas_builder::mirrored)
+const MIRRORED_SUFFIX: &str = "-mirrored";
+
+// Every accepted charset name, for error messages
+pub fn names() -> Vec<String> {
+    NAMED_CHARSETS
+        .iter()
+        .flat_map(|(name, _)| [name.to_string(), format!("{}{}", name, MIRRORED_SUFFIX)])
+        .collect()
+}
+
+// Characters of a built-in charset, e.g. "katakana" or "katakana-mirrored"
+pub fn named(name: &str) -> Option<Vec<char>> {
+    let (base, mirror) = match name.strip_suffix(MIRRORED_SUFFIX) {
+        Some(base) => (base, true),
+        None => (name, false),
+    };
+    let (_, ranges) = NAMED_CHARSETS.iter().find(|(known, _)| *known == base)?;
+    let chars = atlas_builder::parse_ranges(ranges).expect("Invalid built-in charset ranges");
+    if mirror {
+        Some(
+            chars
+                .into_iter()
+                .filter_map(atlas_builder::mirrored)
+                .collect(),
+        )
+    } else {
+        Some(chars)
+    }
+}
+
+// Resolve named charsets, literal characters and Unicode range specs into
+// groups labelled with the set name, "chars" or "ranges"
+pub fn groups<S: AsRef<str>>(
+    sets: &[S],
+    chars: &str,
+    ranges: &[S],
+) -> Result<Vec<(String, Vec<char>)>, String> {
+    let mut groups = Vec::new();
+    for name in sets {
+        let name = name.as_ref();
+        let set = named(name).ok_or_else(|| {
+            format!(
+                "Unknown charset {:?} (expected one of {})",
+                name,
+                names().join(", ")
+            )
+        })?;
+        groups.push((name.to_string(), set));
+    }
+    groups.push((
+        "chars".to_string(),
+        chars.chars().filter(|ch| !ch.is_whitespace()).collect(),
+    ));
+    groups.push(("ranges".to_string(), atlas_builder::parse_ranges(ranges)?));
+    Ok(groups)
+}
+
+// Combine the groups into one charset, keeping the first occurrence of each character
+pub fn combine<S: AsRef<str>>(sets: &[S], chars: &str, ranges: &[S]) -> Result<Vec<char>, String> {
+    let mut charset: Vec<char> = groups(sets, chars, ranges)?
+        .into_iter()
+        .flat_map(|(_, group)| group)
+        .collect();
+
+    let mut seen = std::collections::HashSet::new();
+    charset.retain(|ch| seen.insert(*ch));
+    Ok(charset)
+}
@@ -0,0 +1,451 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::atlas_builder::MAX_GLYPH_SIZE;
+
+// Default config file, looked up in the working directory
+const DEFAULT_CONFIG_PATH: &str = "matrix.toml";
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub font: FontConfig,
+    pub rain: RainConfig,
+    pub theme: ThemeConfig,
+    pub background: BackgroundConfig,
+    pub charset: CharsetConfig,
+    // Image sprite sheets drawn as extra glyphs, see SpriteSheetConfig
+    pub sprites: Vec<SpriteSheetConfig>,
+    // Rain layers drawn back to front; when empty, [rain] and [charset] make the only layer
+    pub layers: Vec<LayerConfig>,
+}
+
+// One rain simulation, composited over the layers listed before it
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct LayerConfig {
+    pub rain: RainConfig,
+    pub charset: CharsetConfig,
+    // Built-in theme name or theme file path; unset layers follow [theme] and the T key
+    pub theme: Option<String>,
+    // Glyph cell size in logical pixels; [font] glyph_size when unset
+    pub glyph_size: Option<u32>,
+    // Mix the in_charset sprite sheets into this layer's falling characters
+    pub sprites: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct FontConfig {
+    // Pre-built atlas from matrix-atlas (.atlas) or BMFont (.fnt); takes precedence over fonts
+    pub atlas: Option<PathBuf>,
+    // TTF/OTF file rasterized at startup; the embedded atlas is used when unset
+    pub path: Option<PathBuf>,
+    // Fonts searched in order for glyphs the primary font lacks
+    pub fallbacks: Vec<PathBuf>,
+    pub glyph_size: u32,
+    // Rasterize glyphs on first use instead of baking the whole charset up front
+    pub glyph_cache: bool,
+    // Atlas pages (texture array layers) reserved for the glyph cache
+    pub cache_pages: u32,
+}
+
+impl Default for FontConfig {
+    fn default() -> Self {
+        Self {
+            atlas: None,
+            path: None,
+            fallbacks: Vec::new(),
+            glyph_size: 32,
+            glyph_cache: false,
+            cache_pages: 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ThemeConfig {
+    // Built-in theme name or theme file path
+    pub name: String,
+    // Themes the T key cycles through; every built-in theme when empty
+    pub cycle: Vec<String>,
+    // Seconds a theme switch takes to fade into the new colors
+    pub transition: f32,
+}
+
+impl Default for ThemeConfig {
+    fn default() -> Self {
+        Self {
+            name: "classic".to_string(),
+            cycle: Vec::new(),
+            transition: 1.0,
+        }
+    }
+}
+
+// Image or video drawn behind the rain; the theme's background color when both are unset
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct BackgroundConfig {
+    pub image: Option<PathBuf>,
+    // Uncompressed YUV4MPEG2 (.y4m) video, looped; takes precedence over the image
+    pub video: Option<PathBuf>,
+    // Scrolling speed in screen widths and heights per second; the image repeats mirrored
+    pub pan: [f32; 2],
+    // Share of the brightness taken away, from 0 (unchanged) to 1 (black)
+    pub dim: f32,
+    // Blur radius in pixels; 0 keeps the image sharp
+    pub blur: f32,
+}
+
+impl Default for BackgroundConfig {
+    fn default() -> Self {
+        Self {
+            image: None,
+            video: None,
+            pan: [0.0, 0.0],
+            dim: 0.5,
+            blur: 0.0,
+        }
+    }
+}
+
+// How the rain is simulated
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RainMode {
+    // Columns of glyphs falling down the screen
+    #[default]
+    Falling,
+    // Glyphs fixed in a grid, lit by brightness waves travelling down each column
+    Grid,
+}
+
+// How rain glyphs combine with what is already drawn beneath them
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlendMode {
+    // Glyphs cover what is beneath by their opacity
+    #[default]
+    Alpha,
+    // Glyphs add their light, so overlaps grow brighter
+    Additive,
+    // Like additive, but brightening saturates instead of clipping
+    Screen,
+    // Alpha blending of premultiplied colors
+    Premultiplied,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct RainConfig {
+    pub mode: RainMode,
+    pub blend: BlendMode,
+    // Grid mode: chance per frame that a cell swaps its glyph
+    pub mutation_chance: f64,
+    // Seconds for a glyph left behind by a drop to fade to half brightness;
+    // 0 ends trails with the drop
+    pub trail_half_life: f32,
+    // Seconds a mutating glyph takes to cross-fade into the new one; 0 swaps instantly
+    pub crossfade: f32,
+    // Relative weight of each drop style: "normal", "headless", "double-head",
+    // "glitter", "dim" and "accent"; styles not listed never appear
+    pub styles: HashMap<String, f32>,
+    // Tail color of accent drops, instead of the theme's
+    pub accent_color: Option<[f32; 3]>,
+    pub falloff: FalloffConfig,
+    pub obstacles: ObstacleConfig,
+    pub splash: SplashConfig,
+}
+
+impl Default for RainConfig {
+    fn default() -> Self {
+        Self {
+            mode: RainMode::Falling,
+            blend: BlendMode::Alpha,
+            mutation_chance: 0.01,
+            trail_half_life: 0.5,
+            crossfade: 0.15,
+            styles: HashMap::from([("normal".to_string(), 1.0)]),
+            accent_color: None,
+            falloff: FalloffConfig::default(),
+            obstacles: ObstacleConfig::default(),
+            splash: SplashConfig::default(),
+        }
+    }
+}
+
+// What a drop does when its head reaches an obstacle
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ObstacleBehavior {
+    // The drop flows into the obstacle and vanishes there
+    #[default]
+    Stop,
+    // Like stop, but each drop leaves a glyph on top, building heaps that melt away
+    Pile,
+    // The drop slides sideways along the obstacle until it can fall again, or
+    // drains into it like a stopped drop at the screen edge or a dead end
+    Slide,
+}
+
+// Falling mode: a mask of cells drops cannot pass, so the rain outlines a logo or word.
+// The mask is centered on the screen.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ObstacleConfig {
+    // Image whose opaque pixels (or bright pixels, without alpha) block drops
+    pub image: Option<PathBuf>,
+    // Text drawn as the mask when there is no image; may span several lines
+    pub text: Option<String>,
+    // Font of the text; the built-in font when unset
+    pub font: Option<PathBuf>,
+    // Share of the screen's width and height the mask may fill, keeping its aspect ratio
+    pub scale: f32,
+    // Coverage from 0 to 1 at which a cell blocks drops
+    pub threshold: f32,
+    pub behavior: ObstacleBehavior,
+    // Pile: seconds a piled glyph stays before it has melted away
+    pub settle: f32,
+    // Color of glyphs drawn in the blocked cells, which stay empty when unset
+    pub fill: Option<[f32; 3]>,
+}
+
+impl Default for ObstacleConfig {
+    fn default() -> Self {
+        Self {
+            image: None,
+            text: None,
+            font: None,
+            scale: 0.6,
+            threshold: 0.3,
+            behavior: ObstacleBehavior::Stop,
+            settle: 3.0,
+            fill: None,
+        }
+    }
+}
+
+// Falling mode: glyph particles scattered where a drop's head reaches the bottom
+// of the screen or an obstacle
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SplashConfig {
+    // Particles per impact; 0 disables splashes
+    pub count: usize,
+    // Seconds a particle takes to fade out
+    pub lifetime: f32,
+    // Launch speed in glyphs per second
+    pub speed: f32,
+    // Downward acceleration in glyphs per second squared
+    pub gravity: f32,
+}
+
+impl Default for SplashConfig {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            lifetime: 0.6,
+            speed: 8.0,
+            gravity: 30.0,
+        }
+    }
+}
+
+// Glyph brightness along a trail: the head, then `hot_length` glyphs following
+// the `hot` segment, then the rest of the tail following `tail`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct FalloffConfig {
+    pub head: f32,
+    pub hot_length: usize,
+    pub hot: FalloffSegment,
+    pub tail: FalloffSegment,
+}
+
+impl Default for FalloffConfig {
+    fn default() -> Self {
+        Self {
+            head: 1.0,
+            hot_length: 0,
+            hot: FalloffSegment {
+                from: 1.0,
+                to: 0.8,
+                ..FalloffSegment::default()
+            },
+            tail: FalloffSegment::default(),
+        }
+    }
+}
+
+// Brightness running from `from` to `to` along a curve
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct FalloffSegment {
+    // "linear", "exponential", "smoothstep", "gamma" or "table"
+    pub curve: String,
+    pub from: f32,
+    pub to: f32,
+    // Steepness of the exponential curve
+    pub rate: f32,
+    // Exponent of the gamma curve
+    pub gamma: f32,
+    // Table curve: [position, progress] points, both from 0 to 1, interpolated linearly
+    pub points: Vec<[f32; 2]>,
+}
+
+impl Default for FalloffSegment {
+    fn default() -> Self {
+        Self {
+            curve: "linear".to_string(),
+            from: 0.8,
+            to: 0.1,
+            rate: 4.0,
+            gamma: 2.2,
+            points: Vec::new(),
+        }
+    }
+}
+
+// Characters the rain is drawn from; the embedded atlas charset when all are empty
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CharsetConfig {
+    // Built-in sets such as "katakana", "digits" or "katakana-mirrored"
+    pub sets: Vec<String>,
+    // Literal characters
+    pub chars: String,
+    // Unicode code points or ranges, e.g. "0041-005A"
+    pub ranges: Vec<String>,
+    // Relative weight of each character in a group, keyed by set name, "chars" or
+    // "ranges"; groups not listed weigh 1
+    pub weights: HashMap<String, f32>,
+    // Dictionary of whitespace-separated words or tokens that drops spell out
+    pub words: Option<PathBuf>,
+    // Share of drops that spell words instead of random glyphs
+    pub words_chance: f64,
+    // Transforms drawn on every glyph of a group, keyed like `weights`:
+    // "flip-x", "flip-y" and "rotate-90"
+    pub transforms: HashMap<String, Vec<String>>,
+    // Chance that a picked glyph also gets one of `random_transforms`
+    pub random_transform_chance: f64,
+    pub random_transforms: Vec<String>,
+}
+
+impl Default for CharsetConfig {
+    fn default() -> Self {
+        Self {
+            sets: Vec::new(),
+            chars: String::new(),
+            ranges: Vec::new(),
+            weights: HashMap::new(),
+            words: None,
+            words_chance: 1.0,
+            transforms: HashMap::new(),
+            random_transform_chance: 0.0,
+            random_transforms: vec![
+                "flip-x".to_string(),
+                "flip-y".to_string(),
+                "rotate-90".to_string(),
+            ],
+        }
+    }
+}
+
+// A sprite sheet whose frames become glyphs mapped to consecutive code points
+// (by default in the Private Use Area, continuing after the previous sheet)
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SpriteSheetConfig {
+    pub image: PathBuf,
+    // Frame rectangles from a JSON file (TexturePacker "hash" or "array" layout);
+    // without it the image is cut into a grid of cell_width x cell_height frames
+    pub frames: Option<PathBuf>,
+    pub cell_width: u32,
+    pub cell_height: u32,
+    // Number of grid frames to use, in row-major order; all cells when unset
+    pub count: Option<u32>,
+    // Code point of the first sprite, e.g. "E000" or "U+E000"
+    pub first_char: Option<String>,
+    // Draw the sprite's own colors instead of tinting it like a font glyph
+    pub keep_colors: bool,
+    // Add the sprites to the rain charset
+    pub in_charset: bool,
+}
+
+impl Default for SpriteSheetConfig {
+    fn default() -> Self {
+        Self {
+            image: PathBuf::new(),
+            frames: None,
+            cell_width: 32,
+            cell_height: 32,
+            count: None,
+            first_char: None,
+            keep_colors: false,
+            in_charset: true,
+        }
+    }
+}
+
+impl Config {
+    // Load config from $MATRIX_CONFIG or ./matrix.toml, falling back to defaults
+    pub fn load() -> Self {
+        let path = std::env::var_os("MATRIX_CONFIG")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH));
+
+        if !path.exists() {
+            return Self::default();
+        }
+
+        match Self::from_file(&path) {
+            Ok(mut config) => {
+                eprintln!("[Config] Loaded {}", path.display());
+                config.font.glyph_size = clamp_glyph_size(config.font.glyph_size);
+                for layer in &mut config.layers {
+                    layer.glyph_size = layer.glyph_size.map(clamp_glyph_size);
+                }
+                config
+            }
+            Err(e) => {
+                eprintln!("[Config] {}, using defaults", e);
+                Self::default()
+            }
+        }
+    }
+
+    // The configured rain layers, back to front
+    pub fn layers(&self) -> Vec<LayerConfig> {
+        if !self.layers.is_empty() {
+            return self.layers.clone();
+        }
+        vec![LayerConfig {
+            rain: self.rain.clone(),
+            charset: self.charset.clone(),
+            theme: None,
+            glyph_size: None,
+            sprites: true,
+        }]
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+    }
+}
+
+// Glyph sizes must be at least a pixel and fit an atlas page
+fn clamp_glyph_size(glyph_size: u32) -> u32 {
+    let clamped = glyph_size.clamp(1, MAX_GLYPH_SIZE);
+    if clamped != glyph_size {
+        eprintln!(
+            "[Config] glyph_size {} is outside 1-{}, using {}",
+            glyph_size, MAX_GLYPH_SIZE, clamped
+        );
+    }
+    clamped
+}
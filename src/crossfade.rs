@@ -0,0 +1,35 @@
+use std::time::{Duration, Instant};
+
+// The glyph a cell is fading out from after it mutated
+#[derive(Clone, Copy, Debug)]
+pub struct GlyphFade {
+    ch: char,
+    // VERTEX_FLIP_* / VERTEX_ROTATE_90 flags
+    transform: u32,
+    started: Instant,
+}
+
+impl GlyphFade {
+    // Start fading out `ch`, unless cross-fading is disabled
+    pub fn start(ch: char, transform: u32, now: Instant, duration: Duration) -> Option<Self> {
+        if duration.is_zero() {
+            return None;
+        }
+        Some(Self {
+            ch,
+            transform,
+            started: now,
+        })
+    }
+
+    // The fading glyph, its transform and the new glyph's share of the blend,
+    // or None once the fade is over
+    pub fn source(&self, now: Instant, duration: Duration) -> Option<(char, u32, f32)> {
+        let elapsed = now.duration_since(self.started);
+        if elapsed >= duration {
+            return None;
+        }
+        let blend = elapsed.as_secs_f32() / duration.as_secs_f32();
+        Some((self.ch, self.transform, blend))
+    }
+}
@@ -0,0 +1,174 @@
+use crate::config::{FalloffConfig, FalloffSegment};
+
+// Shape of a segment, mapping a position from 0 to 1 to progress from 0 to 1
+#[derive(Clone, Debug)]
+enum Curve {
+    Linear,
+    // Drops quickly, then levels out; the rate sets how quickly
+    Exponential(f32),
+    Smoothstep,
+    Gamma(f32),
+    // [position, progress] points sorted by position
+    Table(Vec<[f32; 2]>),
+}
+
+impl Curve {
+    fn parse(segment: &FalloffSegment) -> Result<Self, String> {
+        match segment.curve.as_str() {
+            "linear" => Ok(Curve::Linear),
+            "exponential" => Ok(Curve::Exponential(segment.rate)),
+            "smoothstep" => Ok(Curve::Smoothstep),
+            "gamma" if segment.gamma > 0.0 => Ok(Curve::Gamma(segment.gamma)),
+            "gamma" => Err(format!("Gamma must be positive, got {}", segment.gamma)),
+            "table" if segment.points.is_empty() => {
+                Err("Table falloff curve has no points".to_string())
+            }
+            "table" => {
+                let mut points = segment.points.clone();
+                points.sort_by(|a, b| a[0].total_cmp(&b[0]));
+                Ok(Curve::Table(points))
+            }
+            other => Err(format!(
+                "Unknown falloff curve {:?} (expected linear, exponential, smoothstep, gamma \
+                 or table)",
+                other
+            )),
+        }
+    }
+
+    fn progress(&self, t: f32) -> f32 {
+        match self {
+            Curve::Linear => t,
+            // Too flat to tell from linear, and 1 - e^-rate would divide by ~0
+            Curve::Exponential(rate) if rate.abs() < 1e-3 => t,
+            Curve::Exponential(rate) => (1.0 - (-rate * t).exp()) / (1.0 - (-rate).exp()),
+            Curve::Smoothstep => t * t * (3.0 - 2.0 * t),
+            Curve::Gamma(gamma) => t.powf(*gamma),
+            Curve::Table(points) => {
+                let first = points[0];
+                let last = points[points.len() - 1];
+                if t <= first[0] {
+                    return first[1];
+                }
+                if t >= last[0] {
+                    return last[1];
+                }
+                let end = points.iter().position(|point| point[0] >= t).unwrap();
+                let (a, b) = (points[end - 1], points[end]);
+                let span = b[0] - a[0];
+                if span <= 0.0 {
+                    return b[1];
+                }
+                a[1] + (b[1] - a[1]) * (t - a[0]) / span
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Segment {
+    curve: Curve,
+    from: f32,
+    to: f32,
+}
+
+impl Segment {
+    // Fall back to a linear curve between the same endpoints
+    fn from_config(segment: &FalloffSegment, name: &str) -> Self {
+        let curve = Curve::parse(segment).unwrap_or_else(|e| {
+            eprintln!("[Config] {} in the {} falloff, using linear", e, name);
+            Curve::Linear
+        });
+        Self {
+            curve,
+            from: segment.from,
+            to: segment.to,
+        }
+    }
+
+    fn at(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        self.from + (self.to - self.from) * self.curve.progress(t)
+    }
+}
+
+// Glyph brightness along a drop's trail
+#[derive(Clone, Debug)]
+pub struct Falloff {
+    head: f32,
+    hot_length: usize,
+    hot: Segment,
+    tail: Segment,
+}
+
+impl Falloff {
+    pub fn from_config(config: &FalloffConfig) -> Self {
+        Self {
+            head: config.head.clamp(0.0, 1.0),
+            hot_length: config.hot_length,
+            hot: Segment::from_config(&config.hot, "hot"),
+            tail: Segment::from_config(&config.tail, "tail"),
+        }
+    }
+
+    pub fn head(&self) -> f32 {
+        self.head
+    }
+
+    // Brightness of the glyph `distance` cells behind the head of a trail
+    // `length` cells long, ignoring the head itself; distance 0 takes the
+    // brightness the trail starts with, for drops drawn without a bright head
+    pub fn trail(&self, distance: usize, length: usize) -> f32 {
+        let brightness = if distance <= self.hot_length && self.hot_length > 0 {
+            // Hot glyphs are at distances 1..=hot_length
+            let t = distance.saturating_sub(1) as f32 / (self.hot_length - 1).max(1) as f32;
+            self.hot.at(t)
+        } else {
+            let first = self.hot_length + 1;
+            let tail_length = length.saturating_sub(first).max(2);
+            let t = distance.saturating_sub(first) as f32 / (tail_length - 1) as f32;
+            self.tail.at(t)
+        };
+        brightness.clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(from: f32, to: f32) -> FalloffSegment {
+        FalloffSegment {
+            from,
+            to,
+            ..FalloffSegment::default()
+        }
+    }
+
+    #[test]
+    fn segments_reach_both_endpoints() {
+        let falloff = Falloff::from_config(&FalloffConfig {
+            head: 1.0,
+            hot_length: 4,
+            hot: segment(1.0, 0.6),
+            tail: segment(0.5, 0.1),
+        });
+        // Hot glyphs at distances 1..=4, tail glyphs at 5..=9 of a 10 cell trail
+        assert_eq!(falloff.trail(1, 10), 1.0);
+        assert_eq!(falloff.trail(4, 10), 0.6);
+        assert_eq!(falloff.trail(5, 10), 0.5);
+        assert!((falloff.trail(9, 10) - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn single_hot_glyph_starts_the_segment() {
+        let falloff = Falloff::from_config(&FalloffConfig {
+            head: 1.0,
+            hot_length: 1,
+            hot: segment(0.9, 0.6),
+            tail: segment(0.5, 0.1),
+        });
+        assert_eq!(falloff.trail(1, 10), 0.9);
+        assert_eq!(falloff.trail(2, 10), 0.5);
+    }
+}
@@ -1,34 +1,31 @@
 // Font atlas data - auto-generated at compile time
 include!(concat!(env!("OUT_DIR"), "/font_atlas.rs"));
+include!(concat!(env!("OUT_DIR"), "/charset.rs"));
 
 use std::collections::HashMap;
-use crate::renderer::GlyphMetrics;
+use crate::atlas_builder::GlyphMetrics;
 
 pub struct EmbeddedAtlas {
-    pub png_data: &'static [u8],
+    // One PNG per texture array layer
+    pub png_pages: &'static [&'static [u8]],
+    // Source font of the atlas, the head of runtime fallback chains
+    pub font_data: &'static [u8],
     pub glyph_coordinates: HashMap<char, GlyphMetrics>,
+    pub glyph_size: u32,
+    // Gap between glyph cells, which bounds the mip chain
+    pub padding: u32,
+    pub ascent: f32,
 }
 
 impl EmbeddedAtlas {
     pub fn new() -> Self {
-        let coords = get_glyph_map();
-        let mut glyph_coordinates = HashMap::new();
-        
-        // Convert tuple coordinates to GlyphMetrics
-        for (ch, (u_min, v_min, u_max, v_max)) in coords {
-            glyph_coordinates.insert(ch, GlyphMetrics {
-                u_min,
-                v_min,
-                u_max,
-                v_max,
-                width: 32,
-                height: 32,
-            });
-        }
-        
         Self {
-            png_data: FONT_ATLAS_PNG,
-            glyph_coordinates,
+            png_pages: FONT_ATLAS_PAGES,
+            font_data: FONT_DATA,
+            glyph_coordinates: get_glyph_map(),
+            glyph_size: GLYPH_SIZE,
+            padding: GLYPH_PADDING,
+            ascent: GLYPH_ASCENT,
         }
     }
 }
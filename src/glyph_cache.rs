@@ -0,0 +1,264 @@
+use ab_glyph::FontVec;
+use image::{ImageBuffer, Rgba, RgbaImage};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::atlas_builder::{self, AtlasLayout, GlyphMetrics};
+use crate::mipmap::{self, TexelRect};
+use crate::renderer::write_atlas_image;
+
+#[derive(Copy, Clone, Debug)]
+struct Slot {
+    layer: u32,
+    x: u32,
+    y: u32,
+}
+
+// On-demand glyph cache: glyphs are rasterized the first time they are needed and
+// packed into free atlas cells, evicting the least recently used glyph when full.
+pub struct GlyphCache {
+    fonts: Vec<FontVec>,
+    layout: AtlasLayout,
+    free_slots: Vec<Slot>,
+    slots: HashMap<char, Slot>,
+    // Frame each resident glyph was last requested, mirrored in an ordered set for eviction
+    last_used: HashMap<char, u64>,
+    lru: BTreeSet<(u64, char)>,
+    frame: u64,
+    // Characters no font covers, so they are not retried every frame
+    uncovered: HashSet<char>,
+    overflow_reported: bool,
+    // Mip levels of the atlas texture, refreshed for every uploaded cell
+    mip_levels: u32,
+}
+
+impl GlyphCache {
+    pub fn new(fonts: Vec<FontVec>, layout: AtlasLayout, page_count: u32, mip_levels: u32) -> Self {
+        let mut free_slots: Vec<Slot> = (0..page_count)
+            .flat_map(|layer| {
+                atlas_builder::cell_origins(&layout).map(move |(x, y)| Slot { layer, x, y })
+            })
+            .collect();
+        // Hand out cells in packing order
+        free_slots.reverse();
+
+        Self {
+            fonts,
+            layout,
+            free_slots,
+            slots: HashMap::new(),
+            last_used: HashMap::new(),
+            lru: BTreeSet::new(),
+            frame: 0,
+            uncovered: HashSet::new(),
+            overflow_reported: false,
+            mip_levels,
+        }
+    }
+
+    pub fn ascent(&self) -> f32 {
+        atlas_builder::ascent(&self.fonts, self.layout.glyph_size)
+    }
+
+    pub fn is_covered(&self, ch: char) -> bool {
+        atlas_builder::is_covered(&self.fonts, ch)
+    }
+
+    // Make every requested glyph resident, uploading newly rasterized cells to the
+    // atlas texture. Glyphs requested this frame are never evicted.
+    pub fn prepare(
+        &mut self,
+        chars: impl IntoIterator<Item = char>,
+        glyph_map: &mut HashMap<char, GlyphMetrics>,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+    ) {
+        for (bitmap, slot) in self.place_glyphs(chars, glyph_map) {
+            self.upload(&bitmap, slot, queue, texture);
+        }
+    }
+
+    // Assign cells to the requested glyphs for a new frame, returning the bitmaps
+    // of the glyphs that were not resident yet
+    fn place_glyphs(
+        &mut self,
+        chars: impl IntoIterator<Item = char>,
+        glyph_map: &mut HashMap<char, GlyphMetrics>,
+    ) -> Vec<(RgbaImage, Slot)> {
+        self.frame += 1;
+
+        let mut placed = Vec::new();
+        for ch in chars {
+            if self.slots.contains_key(&ch) {
+                self.touch(ch);
+                continue;
+            }
+            if self.uncovered.contains(&ch) {
+                continue;
+            }
+
+            let Some(glyph) =
+                atlas_builder::rasterize_glyph(&self.fonts, ch, self.layout.glyph_size)
+            else {
+                self.uncovered.insert(ch);
+                continue;
+            };
+
+            let Some(slot) = self.allocate(glyph_map) else {
+                if !self.overflow_reported {
+                    eprintln!("[Glyph Cache] Atlas full, some glyphs will not be drawn this frame");
+                    self.overflow_reported = true;
+                }
+                continue;
+            };
+
+            let metrics =
+                atlas_builder::place_glyph(&glyph, &self.layout, slot.layer, slot.x, slot.y);
+            glyph_map.insert(ch, metrics);
+            self.slots.insert(ch, slot);
+            self.touch(ch);
+            placed.push((glyph.bitmap, slot));
+        }
+        placed
+    }
+
+    fn touch(&mut self, ch: char) {
+        if let Some(previous) = self.last_used.insert(ch, self.frame) {
+            self.lru.remove(&(previous, ch));
+        }
+        self.lru.insert((self.frame, ch));
+    }
+
+    // Take a free cell, or evict the least recently used glyph not needed this frame
+    fn allocate(&mut self, glyph_map: &mut HashMap<char, GlyphMetrics>) -> Option<Slot> {
+        if let Some(slot) = self.free_slots.pop() {
+            return Some(slot);
+        }
+
+        let &(last_used, victim) = self.lru.first()?;
+        if last_used == self.frame {
+            return None;
+        }
+
+        self.lru.remove(&(last_used, victim));
+        self.last_used.remove(&victim);
+        glyph_map.remove(&victim);
+        self.slots.remove(&victim)
+    }
+
+    // Upload only the glyph's cell; the full cell is written so no texels of an
+    // evicted glyph remain next to the new bitmap
+    fn upload(&self, bitmap: &RgbaImage, slot: Slot, queue: &wgpu::Queue, texture: &wgpu::Texture) {
+        let glyph_size = self.layout.glyph_size;
+        let mut cell: RgbaImage = ImageBuffer::from_pixel(glyph_size, glyph_size, Rgba([0, 0, 0, 0]));
+        image::imageops::replace(&mut cell, bitmap, 0, 0);
+        write_atlas_image(queue, texture, slot.layer, 0, slot.x, slot.y, &cell);
+
+        if self.mip_levels > 1 {
+            self.upload_mips(&cell, slot, queue, texture);
+        }
+    }
+
+    // Downsample the cell into the lower mip levels. The cell is placed in a region
+    // aligned to the coarsest level so its texels line up with the rest of the page.
+    fn upload_mips(
+        &self,
+        cell: &RgbaImage,
+        slot: Slot,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+    ) {
+        let glyph_size = self.layout.glyph_size;
+        let align = 1 << (self.mip_levels - 1);
+        let (origin_x, origin_y) = (slot.x / align * align, slot.y / align * align);
+        let cell_rect = TexelRect {
+            x: slot.x - origin_x,
+            y: slot.y - origin_y,
+            width: glyph_size,
+            height: glyph_size,
+        };
+
+        let mut region = RgbaImage::new(
+            (slot.x + glyph_size).div_ceil(align) * align - origin_x,
+            (slot.y + glyph_size).div_ceil(align) * align - origin_y,
+        );
+        image::imageops::replace(&mut region, cell, cell_rect.x as i64, cell_rect.y as i64);
+
+        let mips = mipmap::generate_mips(&region, &[cell_rect], self.mip_levels);
+        for (i, mip) in mips.iter().enumerate() {
+            let level = i as u32 + 1;
+            // Only the cell's own texels, so neighbouring glyphs are left alone
+            let rect = cell_rect.at_level(level);
+            let texels = image::imageops::crop_imm(mip, rect.x, rect.y, rect.width, rect.height);
+            write_atlas_image(
+                queue,
+                texture,
+                slot.layer,
+                level,
+                (origin_x >> level) + rect.x,
+                (origin_y >> level) + rect.y,
+                &texels.to_image(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A cache over the bundled font with room for two 16 px glyphs
+    fn two_slot_cache() -> GlyphCache {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/font/matrix code nfi.ttf");
+        let font = FontVec::try_from_vec(std::fs::read(path).unwrap()).unwrap();
+        let layout = AtlasLayout {
+            width: 56,
+            height: 32,
+            glyph_size: 16,
+            padding: 8,
+        };
+        GlyphCache::new(vec![font], layout, 1, 1)
+    }
+
+    fn resident(glyph_map: &HashMap<char, GlyphMetrics>) -> Vec<char> {
+        let mut chars: Vec<char> = glyph_map.keys().copied().collect();
+        chars.sort();
+        chars
+    }
+
+    #[test]
+    fn evicts_least_recently_used_glyph() {
+        let mut cache = two_slot_cache();
+        let mut glyph_map = HashMap::new();
+        cache.place_glyphs(['A'], &mut glyph_map);
+        cache.place_glyphs(['B'], &mut glyph_map);
+        let placed = cache.place_glyphs(['C'], &mut glyph_map);
+        assert_eq!(placed.len(), 1);
+        assert_eq!(resident(&glyph_map), ['B', 'C']);
+        assert!(!cache.slots.contains_key(&'A'));
+    }
+
+    #[test]
+    fn keeps_glyphs_used_this_frame() {
+        let mut cache = two_slot_cache();
+        let mut glyph_map = HashMap::new();
+        let placed = cache.place_glyphs(['A', 'B', 'C'], &mut glyph_map);
+        assert_eq!(placed.len(), 2);
+        assert_eq!(resident(&glyph_map), ['A', 'B']);
+
+        // C only gets a cell once A or B goes unused for a frame
+        cache.place_glyphs(['B', 'C'], &mut glyph_map);
+        assert_eq!(resident(&glyph_map), ['B', 'C']);
+    }
+
+    #[test]
+    fn requested_glyph_becomes_most_recently_used() {
+        let mut cache = two_slot_cache();
+        let mut glyph_map = HashMap::new();
+        cache.place_glyphs(['A'], &mut glyph_map);
+        cache.place_glyphs(['B'], &mut glyph_map);
+        // Already resident, so only its last use moves
+        assert!(cache.place_glyphs(['A'], &mut glyph_map).is_empty());
+        cache.place_glyphs(['C'], &mut glyph_map);
+        assert_eq!(resident(&glyph_map), ['A', 'C']);
+    }
+}
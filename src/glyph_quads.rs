@@ -0,0 +1,213 @@
+use crate::atlas_builder::GlyphMetrics;
+use crate::renderer::{
+    FontAtlas, Vertex, VERTEX_FLIP_X, VERTEX_FLIP_Y, VERTEX_KEEP_COLORS, VERTEX_ROTATE_90,
+};
+
+// Quad edges in pixels, relative to the cell's center
+#[derive(Clone, Copy, Debug)]
+struct QuadRect {
+    left: f32,
+    right: f32,
+    top: f32,
+    bottom: f32,
+}
+
+impl QuadRect {
+    fn union(&self, other: &QuadRect) -> QuadRect {
+        QuadRect {
+            left: self.left.min(other.left),
+            right: self.right.max(other.right),
+            top: self.top.min(other.top),
+            bottom: self.bottom.max(other.bottom),
+        }
+    }
+
+    // Quad corner coordinates of a point, (0, 0) at the top-left of this rect and
+    // (1, 1) at the bottom-right; empty rects put every point outside
+    fn corner_at(&self, x: f32, y: f32) -> [f32; 2] {
+        [
+            (x - self.left) / (self.right - self.left).max(f32::EPSILON),
+            (y - self.top) / (self.bottom - self.top).max(f32::EPSILON),
+        ]
+    }
+}
+
+// One glyph mapped onto a quad
+struct QuadGlyph<'m> {
+    metrics: &'m GlyphMetrics,
+    flags: u32,
+    rect: QuadRect,
+}
+
+impl QuadGlyph<'_> {
+    fn uv_rect(&self) -> [f32; 4] {
+        [
+            self.metrics.u_min,
+            self.metrics.v_min,
+            self.metrics.u_max,
+            self.metrics.v_max,
+        ]
+    }
+}
+
+// Collects the quads of glyphs drawn in square cells, shared by every simulation mode
+pub struct GlyphQuads<'a> {
+    font_atlas: &'a FontAtlas,
+    width: f32,
+    height: f32,
+    glyph_size: f32,
+    // Atlas texels to screen pixels
+    texel_scale: f32,
+    baseline_offset: f32,
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+}
+
+impl<'a> GlyphQuads<'a> {
+    pub fn new(font_atlas: &'a FontAtlas, width: usize, height: usize, glyph_size: f32) -> Self {
+        let texel_scale = glyph_size / font_atlas.font_size as f32;
+        Self {
+            font_atlas,
+            width: width as f32,
+            height: height as f32,
+            glyph_size,
+            texel_scale,
+            baseline_offset: font_atlas.ascent * texel_scale,
+            vertices: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    // Look up a glyph and place it in a cell with VERTEX_FLIP_* / VERTEX_ROTATE_90
+    // transform flags. Characters missing from every font were reported at startup.
+    fn glyph(&self, ch: char, transform: u32) -> Option<QuadGlyph<'a>> {
+        let metrics = self.font_atlas.glyph_map.get(&ch)?;
+        let mut flags = transform;
+        if self.font_atlas.keeps_colors(ch) {
+            flags |= VERTEX_KEEP_COLORS;
+        }
+
+        // Place the glyph bitmap on the cell's baseline, centered on its advance
+        let texel_scale = self.texel_scale;
+        let half_cell = self.glyph_size * 0.5;
+        let pen_x = (self.glyph_size - metrics.advance * texel_scale) * 0.5;
+        let mut left = pen_x + metrics.bearing_x * texel_scale - half_cell;
+        let mut right = left + metrics.width as f32 * texel_scale;
+        let mut top = self.baseline_offset + metrics.bearing_y * texel_scale - half_cell;
+        let mut bottom = top + metrics.height as f32 * texel_scale;
+
+        // Mirror and rotate the quad about the cell's center; the shader
+        // maps the glyph onto it to match
+        if transform & VERTEX_FLIP_X != 0 {
+            (left, right) = (-right, -left);
+        }
+        if transform & VERTEX_FLIP_Y != 0 {
+            (top, bottom) = (-bottom, -top);
+        }
+        if transform & VERTEX_ROTATE_90 != 0 {
+            // Clockwise in screen space, where y points down
+            (left, right, top, bottom) = (-bottom, -top, left, right);
+        }
+
+        Some(QuadGlyph {
+            metrics,
+            flags,
+            rect: QuadRect {
+                left,
+                right,
+                top,
+                bottom,
+            },
+        })
+    }
+
+    // Draw `ch` in the cell whose top-left corner is at (cell_x, cell_y) pixels
+    pub fn push(&mut self, ch: char, transform: u32, cell_x: f32, cell_y: f32, color: [f32; 4]) {
+        if let Some(glyph) = self.glyph(ch, transform) {
+            self.push_quad(&glyph, &glyph, 1.0, cell_x, cell_y, color);
+        }
+    }
+
+    // Draw a cell cross-fading to `ch` from the glyph and transform in `from`,
+    // whose last element is the new glyph's share of the blend
+    pub fn push_fading(
+        &mut self,
+        ch: char,
+        transform: u32,
+        from: (char, u32, f32),
+        cell_x: f32,
+        cell_y: f32,
+        color: [f32; 4],
+    ) {
+        let (from_ch, from_transform, blend) = from;
+        match (
+            self.glyph(ch, transform),
+            self.glyph(from_ch, from_transform),
+        ) {
+            (Some(glyph), Some(previous)) => {
+                self.push_quad(&glyph, &previous, blend, cell_x, cell_y, color)
+            }
+            (Some(glyph), None) => self.push_quad(&glyph, &glyph, 1.0, cell_x, cell_y, color),
+            (None, _) => {}
+        }
+    }
+
+    // Add one quad covering both glyphs; each is masked to its own rectangle in
+    // the fragment shader
+    fn push_quad(
+        &mut self,
+        glyph: &QuadGlyph,
+        previous: &QuadGlyph,
+        blend: f32,
+        cell_x: f32,
+        cell_y: f32,
+        color: [f32; 4],
+    ) {
+        let quad = glyph.rect.union(&previous.rect);
+        let center_x = cell_x + self.glyph_size * 0.5;
+        let center_y = cell_y + self.glyph_size * 0.5;
+        let (uv_rect, prev_uv_rect) = (glyph.uv_rect(), previous.uv_rect());
+
+        let vertex = |x: f32, y: f32| Vertex {
+            // Convert pixel coords to NDC
+            position: [
+                (2.0 * (center_x + x) / self.width) - 1.0,
+                1.0 - (2.0 * (center_y + y) / self.height),
+            ],
+            corner: glyph.rect.corner_at(x, y),
+            color,
+            layer: glyph.metrics.layer,
+            flags: glyph.flags,
+            uv_rect,
+            prev_corner: previous.rect.corner_at(x, y),
+            prev_uv_rect,
+            prev_layer: previous.metrics.layer,
+            prev_flags: previous.flags,
+            blend,
+        };
+
+        // Add quad vertices (2 triangles): bottom-left, bottom-right, top-left, top-right
+        let base_idx = self.vertices.len() as u32;
+        self.vertices.extend([
+            vertex(quad.left, quad.bottom),
+            vertex(quad.right, quad.bottom),
+            vertex(quad.left, quad.top),
+            vertex(quad.right, quad.top),
+        ]);
+
+        // First triangle (bottom-left, bottom-right, top-left),
+        // second triangle (bottom-right, top-right, top-left)
+        self.indices.extend([
+            base_idx,
+            base_idx + 1,
+            base_idx + 2,
+            base_idx + 1,
+            base_idx + 3,
+            base_idx + 2,
+        ]);
+    }
+
+    pub fn finish(self) -> (Vec<Vertex>, Vec<u32>) {
+        (self.vertices, self.indices)
+    }
+}
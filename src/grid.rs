@@ -0,0 +1,252 @@
+use rand::Rng;
+use std::time::{Duration, Instant};
+
+use crate::config::RainConfig;
+use crate::crossfade::GlyphFade;
+use crate::glyph_quads::GlyphQuads;
+use crate::rain_charset::RainCharset;
+use crate::renderer::{FontAtlas, Vertex};
+use crate::styles::{glitter_lit, DropStyle, DropStyles};
+use crate::themes::Palette;
+use crate::trails::TrailDecay;
+
+// A glyph fixed in place; it only changes when it mutates
+#[derive(Clone, Copy, Debug)]
+struct Cell {
+    ch: char,
+    // VERTEX_FLIP_* / VERTEX_ROTATE_90 flags
+    transform: u32,
+    // The glyph still cross-fading out after a mutation
+    fade: Option<GlyphFade>,
+}
+
+// A brightness wave travelling down one column
+#[derive(Clone, Copy, Debug)]
+struct Wave {
+    // Row of the bright head; negative while it is still above the screen
+    head: f32,
+    // Rows per frame
+    speed: f32,
+    // Lit rows behind the head
+    length: usize,
+    style: DropStyle,
+    // Random per wave, so glitter heads flicker out of step
+    seed: u32,
+}
+
+// Stationary glyph mode: glyphs sit in a fixed grid and mutate on their own,
+// while a wave in each column lights them and lets them fade behind it
+pub struct GridSimulation {
+    // Row-major
+    cells: Vec<Cell>,
+    // One per column
+    waves: Vec<Wave>,
+    columns: usize,
+    rows: usize,
+    width: usize,
+    height: usize,
+    // Cell size in physical pixels
+    glyph_size: f32,
+    mutation_chance: f64,
+    rng: rand::rngs::ThreadRng,
+    charset: RainCharset,
+    // Glyphs left lit once a wave has passed
+    trails: TrailDecay,
+    // Cross-fade duration of mutating glyphs
+    crossfade: Duration,
+    // Time of the current frame
+    now: Instant,
+    styles: DropStyles,
+    frame_count: u32,
+}
+
+impl GridSimulation {
+    pub fn new(
+        config: &RainConfig,
+        palette: Palette,
+        width: usize,
+        height: usize,
+        glyph_size: f32,
+        charset: RainCharset,
+    ) -> Self {
+        let mut sim = Self {
+            cells: Vec::new(),
+            waves: Vec::new(),
+            columns: 0,
+            rows: 0,
+            width,
+            height,
+            glyph_size,
+            mutation_chance: config.mutation_chance.clamp(0.0, 1.0),
+            rng: rand::thread_rng(),
+            charset,
+            trails: TrailDecay::new(config.trail_half_life),
+            crossfade: Duration::from_secs_f32(config.crossfade.max(0.0)),
+            now: Instant::now(),
+            styles: DropStyles::from_config(config, palette),
+            frame_count: 0,
+        };
+        sim.fill_grid();
+        sim
+    }
+
+    pub fn palette(&self) -> &Palette {
+        self.styles.palette()
+    }
+
+    pub fn palette_mut(&mut self) -> &mut Palette {
+        self.styles.palette_mut()
+    }
+
+    // Cover the screen with cells and start a wave at a random height in each column
+    fn fill_grid(&mut self) {
+        self.columns = (self.width as f32 / self.glyph_size).ceil() as usize;
+        self.rows = (self.height as f32 / self.glyph_size).ceil() as usize;
+        self.trails.clear();
+
+        self.cells = (0..self.columns * self.rows)
+            .map(|_| {
+                let (ch, transform) = self.charset.pick(&mut self.rng);
+                Cell {
+                    ch,
+                    transform,
+                    fade: None,
+                }
+            })
+            .collect();
+
+        self.waves = (0..self.columns)
+            .map(|_| {
+                let mut wave = self.new_wave();
+                // Spread the first waves over the screen and the area above it
+                wave.head = self
+                    .rng
+                    .gen_range(-(self.rows as f32) * 2.0..=self.rows as f32);
+                wave
+            })
+            .collect();
+    }
+
+    // A wave starting just above the screen, with the falling mode's trail
+    // lengths, speeds and styles
+    fn new_wave(&mut self) -> Wave {
+        let speed_pixels = self.rng.gen_range(2.0..4.0) + self.rng.gen_range(0.0..1.0);
+        let style = self.styles.pick(&mut self.rng);
+        Wave {
+            head: -(self.rng.gen_range(0..=self.rows) as f32),
+            speed: speed_pixels / self.glyph_size,
+            length: style.trail_length(self.rng.gen_range(42..70)),
+            style,
+            seed: self.rng.gen(),
+        }
+    }
+
+    pub fn update(&mut self) {
+        self.frame_count = self.frame_count.wrapping_add(1);
+        self.now = Instant::now();
+        self.trails.begin_frame(self.now);
+        self.styles.palette_mut().update(self.now);
+        for column in 0..self.columns {
+            let wave = &mut self.waves[column];
+            wave.head += wave.speed;
+            // Restart once the end of the trail has left the bottom
+            if wave.head - wave.length as f32 > self.rows as f32 {
+                self.waves[column] = self.new_wave();
+            }
+        }
+
+        // Every cell mutates independently, lit or not
+        for cell in &mut self.cells {
+            if self.rng.gen_bool(self.mutation_chance) {
+                cell.fade = GlyphFade::start(cell.ch, cell.transform, self.now, self.crossfade);
+                (cell.ch, cell.transform) = self.charset.pick(&mut self.rng);
+            }
+        }
+
+        // Lit cells keep their glyph and color once the wave has passed
+        let lit: Vec<_> = self.lit_cells().collect();
+        for (column, row, color) in lit {
+            let cell = self.cells[row * self.columns + column];
+            self.trails.touch(
+                self.cell_x(column),
+                row as i32,
+                cell.ch,
+                cell.transform,
+                color,
+            );
+        }
+    }
+
+    // Left edge of a column in pixels
+    fn cell_x(&self, column: usize) -> usize {
+        (column as f32 * self.glyph_size) as usize
+    }
+
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.fill_grid();
+    }
+
+    // Change the cell size (e.g. after a DPI change) and rebuild the grid
+    pub fn set_glyph_size(&mut self, glyph_size: f32) {
+        if (glyph_size - self.glyph_size).abs() < f32::EPSILON {
+            return;
+        }
+        self.glyph_size = glyph_size;
+        self.fill_grid();
+    }
+
+    // Cells lit by a wave, as (column, row, color in the wave's style)
+    fn lit_cells(&self) -> impl Iterator<Item = (usize, usize, [f32; 4])> + '_ {
+        self.waves
+            .iter()
+            .enumerate()
+            .flat_map(move |(column, wave)| {
+                let head = wave.head.floor() as i64;
+                let lit = glitter_lit(wave.seed, self.frame_count);
+                (0..wave.length).filter_map(move |distance| {
+                    let row = head - distance as i64;
+                    if !(0..self.rows as i64).contains(&row) {
+                        return None;
+                    }
+                    let color = self
+                        .styles
+                        .color(wave.style, distance, wave.length, column, lit);
+                    Some((column, row as usize, color))
+                })
+            })
+    }
+
+    // Characters currently lit, used to fill the on-demand glyph cache
+    pub fn visible_chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.lit_cells()
+            .flat_map(|(column, row, _)| {
+                // Glyphs fading out are drawn too
+                let cell = &self.cells[row * self.columns + column];
+                let fading = cell
+                    .fade
+                    .and_then(|fade| fade.source(self.now, self.crossfade))
+                    .map(|(ch, _, _)| ch);
+                std::iter::once(cell.ch).chain(fading)
+            })
+            .chain(self.trails.visible_chars())
+    }
+
+    pub fn generate_vertex_data(&self, font_atlas: &FontAtlas) -> (Vec<Vertex>, Vec<u32>) {
+        let mut quads = GlyphQuads::new(font_atlas, self.width, self.height, self.glyph_size);
+        self.trails.push_quads(&mut quads, self.glyph_size);
+        for (column, row, color) in self.lit_cells() {
+            let cell = self.cells[row * self.columns + column];
+            let (x, y) = (self.cell_x(column) as f32, row as f32 * self.glyph_size);
+            let fade = cell
+                .fade
+                .and_then(|fade| fade.source(self.now, self.crossfade));
+            match fade {
+                Some(from) => quads.push_fading(cell.ch, cell.transform, from, x, y, color),
+                None => quads.push(cell.ch, cell.transform, x, y, color),
+            }
+        }
+        quads.finish()
+    }
+}
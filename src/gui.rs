@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use winit::event::{ElementState, Event, KeyEvent, WindowEvent};
@@ -5,31 +6,103 @@ use winit::event_loop::EventLoopWindowTarget;
 use winit::keyboard::{KeyCode, PhysicalKey};
 use winit::window::Window;
 
-use crate::rain::RainSimulation;
+use crate::config::Config;
+use crate::layers::RainLayer;
+use crate::rain_charset::RainCharset;
 use crate::renderer::Renderer;
+use crate::simulation::Simulation;
+use crate::themes::{self, Palette, Theme};
 
 const TARGET_FPS: f32 = 75.0;
 const TARGET_FRAME_TIME: Duration = Duration::from_micros((1_000_000.0 / TARGET_FPS) as u64);
 
 pub struct App {
     renderer: Option<Renderer>,
-    rain: RainSimulation,
+    layers: Vec<RainLayer>,
     window: Arc<Window>,
     last_frame_time: Instant,
     frame_count: u32,
+    // Themes the T key cycles through, and the position of the current one
+    themes: Vec<String>,
+    theme_index: Option<usize>,
 }
 
 impl App {
     pub async fn new(window: Arc<Window>) -> Self {
-        let renderer = Renderer::new(window.clone()).await;
-        let rain = RainSimulation::new(1280, 720);
+        let config = Config::load();
+        let layer_configs = config.layers();
+        let charsets: Vec<RainCharset> = layer_configs
+            .iter()
+            .map(|layer| RainCharset::from_config(&layer.charset))
+            .collect();
+
+        // One atlas holds the characters of every layer
+        let mut seen = HashSet::new();
+        let atlas_chars: Vec<char> = charsets
+            .iter()
+            .flat_map(|charset| charset.atlas_chars())
+            .filter(|ch| seen.insert(*ch))
+            .collect();
+        let renderer = Renderer::new(window.clone(), &config, &atlas_chars).await;
+        let sprite_chars = renderer.sprite_charset();
+
+        let theme = load_theme(&config.theme.name);
+        let transition = Duration::from_secs_f32(config.theme.transition.max(0.0));
+        let themes = if config.theme.cycle.is_empty() {
+            themes::BUILTIN_THEMES.iter().map(|name| name.to_string()).collect()
+        } else {
+            config.theme.cycle.clone()
+        };
+        let theme_index = themes.iter().position(|name| *name == config.theme.name);
+
+        let size = window.inner_size();
+        let layers = layer_configs
+            .iter()
+            .zip(charsets)
+            .map(|(layer, mut charset)| {
+                if layer.sprites {
+                    charset.extend(sprite_chars.iter().copied());
+                }
+                let layer_theme = layer.theme.as_deref().map_or(theme.clone(), load_theme);
+                let glyph_size = layer.glyph_size.unwrap_or(config.font.glyph_size);
+                let simulation = Simulation::new(
+                    &layer.rain,
+                    Palette::new(layer_theme, transition),
+                    size.width as usize,
+                    size.height as usize,
+                    glyph_size as f32 * window.scale_factor() as f32,
+                    charset,
+                );
+                RainLayer::new(layer, simulation, glyph_size)
+            })
+            .collect();
 
         Self {
             renderer: Some(renderer),
-            rain,
+            layers,
             window,
             last_frame_time: Instant::now(),
             frame_count: 0,
+            themes,
+            theme_index,
+        }
+    }
+
+    // Fade into the next theme of the cycle
+    fn next_theme(&mut self) {
+        if self.themes.is_empty() {
+            return;
+        }
+        let index = self.theme_index.map_or(0, |index| (index + 1) % self.themes.len());
+        self.theme_index = Some(index);
+        match themes::load(&self.themes[index]) {
+            Ok(theme) => {
+                eprintln!("[Theme] {}", self.themes[index]);
+                for layer in &mut self.layers {
+                    layer.set_theme(&theme);
+                }
+            }
+            Err(e) => eprintln!("[Theme] {}", e),
         }
     }
 
@@ -71,7 +144,20 @@ impl App {
             WindowEvent::Resized(size) => {
                 if let Some(renderer) = &mut self.renderer {
                     renderer.on_window_resized(*size);
-                    self.rain.resize(size.width as usize, size.height as usize);
+                    for layer in &mut self.layers {
+                        layer
+                            .simulation
+                            .resize(size.width as usize, size.height as usize);
+                    }
+                }
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                // Keep glyphs the same logical size on the new display
+                if let Some(renderer) = &mut self.renderer {
+                    renderer.on_scale_factor_changed(*scale_factor);
+                }
+                for layer in &mut self.layers {
+                    layer.set_scale_factor(*scale_factor);
                 }
             }
             WindowEvent::KeyboardInput {
@@ -89,6 +175,17 @@ impl App {
                 };
                 self.window.set_fullscreen(fullscreen);
             }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::KeyT),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.next_theme();
+            }
             WindowEvent::RedrawRequested => {
                 // Implement 75 FPS hard limiter
                 let now = Instant::now();
@@ -103,13 +200,15 @@ impl App {
                 self.frame_count = self.frame_count.wrapping_add(1);
 
                 // Log FPS every 60 frames (approximately every 0.8 seconds at 75 FPS)
-                if self.frame_count % 60 == 0 {
+                if self.frame_count.is_multiple_of(60) {
                     eprintln!("Frame: {}", self.frame_count);
                 }
 
-                self.rain.update();
+                for layer in &mut self.layers {
+                    layer.simulation.update();
+                }
                 if let Some(renderer) = &mut self.renderer {
-                    match renderer.render_frame(&self.rain) {
+                    match renderer.render_frame(&self.layers) {
                         Ok(_) => {}
                         Err(wgpu::SurfaceError::Lost) => {
                             renderer.resize_framebuffers();
@@ -129,3 +228,11 @@ impl App {
         }
     }
 }
+
+// A built-in theme or theme file, or the classic theme when it fails to load
+fn load_theme(name: &str) -> Theme {
+    themes::load(name).unwrap_or_else(|e| {
+        eprintln!("[Config] {}, using the classic theme", e);
+        Theme::default()
+    })
+}
@@ -0,0 +1,37 @@
+use crate::config::{BlendMode, LayerConfig};
+use crate::simulation::Simulation;
+use crate::themes::Theme;
+
+// A rain simulation and how it is composited over the layers beneath it
+pub struct RainLayer {
+    pub simulation: Simulation,
+    pub blend: BlendMode,
+    // Logical pixels, scaled by the display's DPI factor
+    glyph_size: u32,
+    // Whether the layer takes the theme cycled with T, having none of its own
+    follows_theme: bool,
+}
+
+impl RainLayer {
+    pub fn new(config: &LayerConfig, simulation: Simulation, glyph_size: u32) -> Self {
+        Self {
+            simulation,
+            blend: config.rain.blend,
+            glyph_size,
+            follows_theme: config.theme.is_none(),
+        }
+    }
+
+    // Keep glyphs the same logical size on a display with a new DPI factor
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        self.simulation
+            .set_glyph_size(self.glyph_size as f32 * scale_factor as f32);
+    }
+
+    // Fade into a theme cycled with T, unless the layer has a theme of its own
+    pub fn set_theme(&mut self, theme: &Theme) {
+        if self.follows_theme {
+            self.simulation.set_theme(theme.clone());
+        }
+    }
+}
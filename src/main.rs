@@ -1,8 +1,35 @@
+// Shared with build.rs and matrix-atlas, which each use a different subset
+#[allow(dead_code)]
+mod atlas_builder;
+mod background;
+// Shared with matrix-atlas, which exports what the app imports
+#[allow(dead_code)]
+mod bmfont;
+// Shared with build.rs and matrix-atlas, which only combine charsets
+#[allow(dead_code)]
+mod charsets;
+mod config;
+mod crossfade;
 mod events;
+mod falloff;
 mod font_atlas;
+mod glyph_cache;
+mod glyph_quads;
+mod grid;
 mod gui;
+mod layers;
+mod mipmap;
+mod obstacles;
 mod rain;
+mod rain_charset;
 mod renderer;
+mod simulation;
+mod splashes;
+mod sprites;
+mod styles;
+mod themes;
+mod trails;
+mod y4m;
 
 use std::sync::Arc;
 use winit::event_loop::EventLoop;
@@ -0,0 +1,113 @@
+use image::{Rgba, RgbaImage};
+use std::collections::HashMap;
+
+use crate::atlas_builder::GlyphMetrics;
+
+// Texel rectangle of a glyph on an atlas page
+#[derive(Copy, Clone, Debug)]
+pub struct TexelRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl TexelRect {
+    // The texels covering this rectangle at a lower mip level
+    pub fn at_level(&self, level: u32) -> TexelRect {
+        let scale = 1 << level;
+        let x = self.x / scale;
+        let y = self.y / scale;
+        TexelRect {
+            x,
+            y,
+            width: (self.x + self.width).div_ceil(scale) - x,
+            height: (self.y + self.height).div_ceil(scale) - y,
+        }
+    }
+}
+
+// Glyphs smaller than this are never drawn, so deeper levels would be wasted
+const MIN_GLYPH_SIZE: u32 = 4;
+
+// Each mip level halves the padding around glyphs. Stop while at least one
+// texel of padding is left, so no glyph is ever filtered into its neighbour,
+// or once glyphs have shrunk to MIN_GLYPH_SIZE.
+pub fn level_count(padding: u32, glyph_size: u32, width: u32, height: u32) -> u32 {
+    let padding_levels = padding.checked_ilog2().map_or(1, |log| log + 1);
+    let glyph_levels = (glyph_size / MIN_GLYPH_SIZE).max(1).ilog2() + 1;
+    let size_levels = width.max(height).ilog2() + 1;
+    padding_levels.min(glyph_levels).min(size_levels)
+}
+
+// Rectangles of the glyphs stored on one atlas page
+pub fn glyph_rects(
+    glyph_map: &HashMap<char, GlyphMetrics>,
+    layer: u32,
+    width: u32,
+    height: u32,
+) -> Vec<TexelRect> {
+    glyph_map
+        .values()
+        .filter(|metrics| metrics.layer == layer)
+        .map(|metrics| TexelRect {
+            x: (metrics.u_min * width as f32).round() as u32,
+            y: (metrics.v_min * height as f32).round() as u32,
+            width: metrics.width,
+            height: metrics.height,
+        })
+        .collect()
+}
+
+// Downsample a page into mip levels 1..levels. Only texels covering a glyph
+// rectangle are filtered and everything else stays transparent, so glyphs never
+// pick up texels from their neighbours.
+pub fn generate_mips(page: &RgbaImage, rects: &[TexelRect], levels: u32) -> Vec<RgbaImage> {
+    let mut mips: Vec<RgbaImage> = Vec::new();
+
+    for level in 1..levels {
+        let source = mips.last().unwrap_or(page);
+        let width = (page.width() >> level).max(1);
+        let height = (page.height() >> level).max(1);
+
+        let mut mip = RgbaImage::new(width, height);
+        for rect in rects {
+            let rect = rect.at_level(level);
+            for y in rect.y..(rect.y + rect.height).min(height) {
+                for x in rect.x..(rect.x + rect.width).min(width) {
+                    mip.put_pixel(x, y, box_filter(source, x * 2, y * 2));
+                }
+            }
+        }
+        mips.push(mip);
+    }
+    mips
+}
+
+// Average a 2x2 block weighted by alpha, so transparent texels around a glyph
+// do not darken its edges
+fn box_filter(source: &RgbaImage, x: u32, y: u32) -> Rgba<u8> {
+    let mut color = [0u32; 3];
+    let mut alpha = 0u32;
+    for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+        let texel = source.get_pixel(
+            (x + dx).min(source.width() - 1),
+            (y + dy).min(source.height() - 1),
+        );
+        let a = texel[3] as u32;
+        for (sum, &channel) in color.iter_mut().zip(&texel.0[..3]) {
+            *sum += channel as u32 * a;
+        }
+        alpha += a;
+    }
+
+    if alpha == 0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    Rgba([
+        (color[0] / alpha) as u8,
+        (color[1] / alpha) as u8,
+        (color[2] / alpha) as u8,
+        ((alpha + 2) / 4) as u8,
+    ])
+}
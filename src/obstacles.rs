@@ -0,0 +1,401 @@
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GrayImage, Luma};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::config::{ObstacleBehavior, ObstacleConfig};
+use crate::font_atlas::EmbeddedAtlas;
+use crate::glyph_quads::GlyphQuads;
+use crate::rain::Raindrop;
+
+// Pixel size text is rasterized at before it is scaled down to cells
+const TEXT_SIZE: f32 = 128.0;
+
+// How a drop is meeting the obstacles
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Contact {
+    #[default]
+    Falling,
+    // Glyphs at and below this row have flowed into an obstacle
+    Absorbed(i32),
+    // Moving sideways along an obstacle, -1 to the left or 1 to the right
+    Sliding(i32),
+}
+
+// What happened to a drop this frame
+pub enum Step {
+    Moved,
+    // The head reached an obstacle, stopping in this (column, row) cell
+    Landed(usize, i32),
+}
+
+// A glyph a drop left on top of an obstacle
+struct PiledGlyph {
+    ch: char,
+    transform: u32,
+    color: [f32; 4],
+    landed: Instant,
+}
+
+// Glyphs heaped in a column, from the `base` row upwards
+struct Pile {
+    base: i32,
+    glyphs: Vec<PiledGlyph>,
+}
+
+impl Pile {
+    fn top(&self) -> i32 {
+        self.base + 1 - self.glyphs.len() as i32
+    }
+
+    fn covers(&self, row: i32) -> bool {
+        row >= self.top() && row <= self.base
+    }
+}
+
+// Cells of the screen that drops cannot pass, fitted from an image or text
+pub struct Obstacles {
+    // Coverage of the mask before it is fitted to the screen
+    source: GrayImage,
+    scale: f32,
+    threshold: f32,
+    behavior: ObstacleBehavior,
+    settle: Duration,
+    fill: Option<[f32; 3]>,
+    columns: usize,
+    rows: usize,
+    // Row-major, one per cell
+    blocked: Vec<bool>,
+    // (column, row, glyph, transform) drawn in blocked cells with the fill color
+    fill_glyphs: Vec<(usize, i32, char, u32)>,
+    // One per column, so looking up whether a cell is piled on is cheap
+    piles: Vec<Option<Pile>>,
+}
+
+impl Obstacles {
+    // The configured obstacles, or None when there are none or they fail to load.
+    // The mask is empty until the first resize.
+    pub fn from_config(config: &ObstacleConfig) -> Option<Self> {
+        let source = match load_source(config) {
+            Ok(source) => source?,
+            Err(e) => {
+                eprintln!("[Config] {}, drops fall without obstacles", e);
+                return None;
+            }
+        };
+        Some(Self {
+            source,
+            scale: config.scale.clamp(0.0, 1.0),
+            threshold: config.threshold,
+            behavior: config.behavior,
+            settle: Duration::from_secs_f32(config.settle.max(0.0)),
+            fill: config.fill,
+            columns: 0,
+            rows: 0,
+            blocked: Vec::new(),
+            fill_glyphs: Vec::new(),
+            piles: Vec::new(),
+        })
+    }
+
+    // Fit the mask to a screen of glyph_size cells, picking the fill glyphs anew
+    pub fn resize(
+        &mut self,
+        width: usize,
+        height: usize,
+        glyph_size: f32,
+        mut pick: impl FnMut() -> (char, u32),
+    ) {
+        self.columns = (width as f32 / glyph_size).ceil() as usize;
+        self.rows = (height as f32 / glyph_size).ceil() as usize;
+        self.blocked = fit_mask(
+            &self.source,
+            self.columns,
+            self.rows,
+            self.scale,
+            self.threshold,
+        );
+        self.piles.clear();
+        self.piles.resize_with(self.columns, || None);
+        self.fill_glyphs.clear();
+        if self.fill.is_some() {
+            for (index, _) in self.blocked.iter().enumerate().filter(|(_, &b)| b) {
+                let (ch, transform) = pick();
+                let (column, row) = (index % self.columns, (index / self.columns) as i32);
+                self.fill_glyphs.push((column, row, ch, transform));
+            }
+        }
+    }
+
+    // Whether the mask or a pile fills a cell
+    fn blocked(&self, column: usize, row: i32) -> bool {
+        let in_mask = column < self.columns
+            && (0..self.rows as i32).contains(&row)
+            && self.blocked[row as usize * self.columns + column];
+        let piled = || {
+            let pile = self.piles.get(column).and_then(Option::as_ref);
+            pile.is_some_and(|pile| pile.covers(row))
+        };
+        in_mask || piled()
+    }
+
+    // Whether the glyph of a drop drawn at char_y is inside an obstacle
+    pub fn hides(&self, raindrop: &Raindrop, char_y: f32, glyph_size: f32) -> bool {
+        let row = row_of(char_y, glyph_size);
+        let absorbed = matches!(raindrop.contact, Contact::Absorbed(floor) if row >= floor);
+        absorbed || self.blocked(column_of(raindrop.x, glyph_size), row)
+    }
+
+    // Move a drop by its speed, stopping or sliding it where its head meets an obstacle
+    pub fn advance(&self, raindrop: &mut Raindrop, glyph_size: f32, width: usize) -> Step {
+        let column = column_of(raindrop.x, glyph_size);
+        let head_row = row_of(raindrop.y as f32, glyph_size);
+        if let Contact::Sliding(direction) = raindrop.contact {
+            if self.blocked(column, head_row + 1) {
+                let x = raindrop.x as i32 + direction * (raindrop.speed as i32).max(1);
+                // Off the screen edge or into a dead end, the drop drains into
+                // the obstacle like one that stopped
+                if x < 0
+                    || x >= width as i32
+                    || self.blocked(column_of(x as usize, glyph_size), head_row)
+                {
+                    raindrop.contact = Contact::Absorbed(head_row + 1);
+                    return Step::Moved;
+                }
+                raindrop.x = x as usize;
+                return Step::Moved;
+            }
+            raindrop.contact = Contact::Falling;
+        }
+
+        raindrop.y += raindrop.speed as i32;
+        if !matches!(raindrop.contact, Contact::Falling) {
+            return Step::Moved;
+        }
+        let new_row = row_of(raindrop.y as f32, glyph_size);
+        let Some(row) = (head_row + 1..=new_row).find(|&row| self.blocked(column, row)) else {
+            return Step::Moved;
+        };
+        match self.behavior {
+            ObstacleBehavior::Stop | ObstacleBehavior::Pile => {
+                raindrop.contact = Contact::Absorbed(row);
+            }
+            ObstacleBehavior::Slide => {
+                raindrop.y = ((row - 1) as f32 * glyph_size) as i32;
+                let direction = if raindrop.seed & 1 == 0 { -1 } else { 1 };
+                raindrop.contact = Contact::Sliding(direction);
+            }
+        }
+        Step::Landed(column, row - 1)
+    }
+
+    // Pile: heap a glyph in the cell a drop landed in
+    pub fn land(&mut self, cell: (usize, i32), glyph: (char, u32), color: [f32; 4], now: Instant) {
+        let (column, row) = cell;
+        if self.behavior != ObstacleBehavior::Pile || row < 0 {
+            return;
+        }
+        let glyph = PiledGlyph {
+            ch: glyph.0,
+            transform: glyph.1,
+            color,
+            landed: now,
+        };
+        match self.piles.get_mut(column) {
+            // A drop stopping on a ledge above the column's pile leaves nothing
+            Some(Some(pile)) if pile.top() == row + 1 => pile.glyphs.push(glyph),
+            Some(slot @ None) => {
+                *slot = Some(Pile {
+                    base: row,
+                    glyphs: vec![glyph],
+                })
+            }
+            _ => {}
+        }
+    }
+
+    // Melt piled glyphs that have settled for long enough; the glyphs above sink
+    pub fn update(&mut self, now: Instant) {
+        let settle = self.settle;
+        for slot in &mut self.piles {
+            if let Some(pile) = slot {
+                pile.glyphs
+                    .retain(|glyph| now.duration_since(glyph.landed) < settle);
+                if pile.glyphs.is_empty() {
+                    *slot = None;
+                }
+            }
+        }
+    }
+
+    // Characters of the fill and the piles, used to fill the on-demand glyph cache
+    pub fn visible_chars(&self) -> impl Iterator<Item = char> + '_ {
+        let fill = self.fill_glyphs.iter().map(|&(_, _, ch, _)| ch);
+        let piles = self
+            .piles
+            .iter()
+            .flatten()
+            .flat_map(|pile| pile.glyphs.iter().map(|glyph| glyph.ch));
+        fill.chain(piles)
+    }
+
+    // Draw the fill glyphs and the piles, which dim as they melt
+    pub fn push_quads(&self, quads: &mut GlyphQuads, glyph_size: f32, now: Instant) {
+        if let Some([r, g, b]) = self.fill {
+            for &(column, row, ch, transform) in &self.fill_glyphs {
+                let (x, y) = (column as f32 * glyph_size, row as f32 * glyph_size);
+                quads.push(ch, transform, x, y, [r, g, b, 1.0]);
+            }
+        }
+
+        let settle = self.settle.as_secs_f32().max(f32::EPSILON);
+        for (column, pile) in self.piles.iter().enumerate() {
+            let Some(pile) = pile else {
+                continue;
+            };
+            for (height, glyph) in pile.glyphs.iter().enumerate() {
+                let melted = now.duration_since(glyph.landed).as_secs_f32() / settle;
+                let color = glyph.color.map(|channel| channel * (1.0 - melted).max(0.0));
+                let x = column as f32 * glyph_size;
+                let y = (pile.base - height as i32) as f32 * glyph_size;
+                quads.push(glyph.ch, glyph.transform, x, y, color);
+            }
+        }
+    }
+}
+
+// Column of the cell under the center of a glyph drawn at x
+fn column_of(x: usize, glyph_size: f32) -> usize {
+    ((x as f32 + glyph_size * 0.5) / glyph_size) as usize
+}
+
+// Row of the cell under the center of a glyph drawn at y
+fn row_of(y: f32, glyph_size: f32) -> i32 {
+    ((y + glyph_size * 0.5) / glyph_size).floor() as i32
+}
+
+fn load_source(config: &ObstacleConfig) -> Result<Option<GrayImage>, String> {
+    if let Some(path) = &config.image {
+        let image =
+            image::open(path).map_err(|e| format!("Failed to load {}: {}", path.display(), e))?;
+        return Ok(Some(coverage(image)));
+    }
+    match &config.text {
+        Some(text) => render_text(text, config.font.as_deref()).map(Some),
+        None => Ok(None),
+    }
+}
+
+// Opaque pixels block, or bright ones in images without alpha
+fn coverage(image: DynamicImage) -> GrayImage {
+    if !image.color().has_alpha() {
+        return image.to_luma8();
+    }
+    let rgba = image.to_rgba8();
+    GrayImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        Luma([rgba.get_pixel(x, y)[3]])
+    })
+}
+
+// Rasterize text, centering each line
+fn render_text(text: &str, font_path: Option<&Path>) -> Result<GrayImage, String> {
+    let font = match font_path {
+        Some(path) => {
+            let data = std::fs::read(path)
+                .map_err(|e| format!("Failed to read font {}: {}", path.display(), e))?;
+            FontVec::try_from_vec(data)
+                .map_err(|e| format!("Failed to parse font {}: {}", path.display(), e))?
+        }
+        None => FontVec::try_from_vec(EmbeddedAtlas::new().font_data.to_vec())
+            .map_err(|e| format!("Failed to parse built-in font: {}", e))?,
+    };
+    let font = font.as_scaled(PxScale::from(TEXT_SIZE));
+
+    let line_width = |line: &str| {
+        let mut width = 0.0;
+        let mut previous = None;
+        for id in line.chars().map(|ch| font.glyph_id(ch)) {
+            if let Some(previous) = previous {
+                width += font.kern(previous, id);
+            }
+            width += font.h_advance(id);
+            previous = Some(id);
+        }
+        width
+    };
+    let lines: Vec<&str> = text.lines().collect();
+    let line_height = font.height() + font.line_gap();
+    let width = lines
+        .iter()
+        .map(|line| line_width(line))
+        .fold(0.0, f32::max)
+        .ceil() as u32;
+    let height = (line_height * lines.len() as f32).ceil() as u32;
+    if width == 0 || height == 0 {
+        return Err("Obstacle text is empty".to_string());
+    }
+
+    let mut image = GrayImage::new(width, height);
+    for (index, line) in lines.iter().enumerate() {
+        let baseline = index as f32 * line_height + font.ascent();
+        let mut caret = (width as f32 - line_width(line)) * 0.5;
+        let mut previous = None;
+        for ch in line.chars() {
+            let id = font.glyph_id(ch);
+            if let Some(previous) = previous {
+                caret += font.kern(previous, id);
+            }
+            previous = Some(id);
+            let glyph = id.with_scale_and_position(font.scale(), ab_glyph::point(caret, baseline));
+            caret += font.h_advance(id);
+
+            let Some(outlined) = font.outline_glyph(glyph) else {
+                continue;
+            };
+            let bounds = outlined.px_bounds();
+            outlined.draw(|x, y, coverage| {
+                let x = bounds.min.x as i32 + x as i32;
+                let y = bounds.min.y as i32 + y as i32;
+                if x >= 0 && y >= 0 && (x as u32) < width && (y as u32) < height {
+                    let pixel = image.get_pixel_mut(x as u32, y as u32);
+                    pixel[0] = pixel[0].max((coverage * 255.0) as u8);
+                }
+            });
+        }
+    }
+    Ok(image)
+}
+
+// Scale the source to fit `scale` of the screen's cells, centered, and block
+// the cells it covers at least `threshold` of
+fn fit_mask(
+    source: &GrayImage,
+    columns: usize,
+    rows: usize,
+    scale: f32,
+    threshold: f32,
+) -> Vec<bool> {
+    let mut blocked = vec![false; columns * rows];
+    let (source_width, source_height) = (source.width() as f32, source.height() as f32);
+    let fit = (columns as f32 * scale / source_width).min(rows as f32 * scale / source_height);
+    let mask_columns = ((source_width * fit).round() as usize).min(columns);
+    let mask_rows = ((source_height * fit).round() as usize).min(rows);
+    if mask_columns == 0 || mask_rows == 0 {
+        return blocked;
+    }
+
+    let cells = imageops::resize(
+        source,
+        mask_columns as u32,
+        mask_rows as u32,
+        FilterType::Triangle,
+    );
+    let (left, top) = ((columns - mask_columns) / 2, (rows - mask_rows) / 2);
+    for (x, y, coverage) in cells.enumerate_pixels() {
+        if coverage[0] as f32 / 255.0 >= threshold {
+            blocked[(top + y as usize) * columns + left + x as usize] = true;
+        }
+    }
+    blocked
+}
@@ -1,7 +1,18 @@
 use rand::Rng;
+use std::time::{Duration, Instant};
+
+use crate::config::RainConfig;
+use crate::crossfade::GlyphFade;
+use crate::glyph_quads::GlyphQuads;
+use crate::obstacles::{Contact, Obstacles, Step};
+use crate::rain_charset::RainCharset;
+use crate::splashes::Splashes;
+use crate::styles::{glitter_lit, DropStyle, DropStyles};
+use crate::themes::Palette;
+use crate::trails::TrailDecay;
 
 // Re-export for use in renderer
-pub use crate::renderer::{GlyphMetrics, Vertex};
+pub use crate::renderer::{FontAtlas, Vertex};
 
 #[derive(Clone, Copy, Debug)]
 pub struct Raindrop {
@@ -10,68 +21,180 @@ pub struct Raindrop {
     pub length: usize,
     pub speed: f32,
     pub chars: [char; 80],
+    // VERTEX_FLIP_* / VERTEX_ROTATE_90 flags of each glyph
+    pub transforms: [u32; 80],
+    // Glyphs still cross-fading out after a mutation
+    pub fades: [Option<GlyphFade>; 80],
     pub char_count: usize,
     pub last_midchain_frame: u32,
+    // Spells dictionary words, so its glyphs are never swapped
+    pub spelled: bool,
+    pub style: DropStyle,
+    // Random per drop, so glitter heads flicker out of step
+    pub seed: u32,
+    pub contact: Contact,
 }
 
 pub struct RainSimulation {
     raindrops: Vec<Raindrop>,
     width: usize,
     height: usize,
+    // Glyph cell size in physical pixels (row spacing and quad size)
+    glyph_size: f32,
     virtual_height: usize,
     frame_count: u32,
     rng: rand::rngs::ThreadRng,
-    charset: Vec<char>,
+    charset: RainCharset,
     last_animation_frame: u32,
+    // Glyphs left behind by passing drops
+    trails: TrailDecay,
+    // Cross-fade duration of mutating glyphs
+    crossfade: Duration,
+    // Time of the current frame
+    now: Instant,
+    styles: DropStyles,
+    // Boxed, as most configs have none
+    obstacles: Option<Box<Obstacles>>,
+    splashes: Splashes,
 }
 
-// Half-width katakana: U+FF66 to U+FF9D (58 characters)
-fn get_charset() -> Vec<char> {
-    (0xFF66..=0xFF9D)
-        .filter_map(char::from_u32)
-        .collect()
+// Characters baked into the embedded atlas (see font/atlas.toml)
+pub fn get_charset() -> Vec<char> {
+    crate::font_atlas::CHARSET.chars().collect()
+}
+
+// Rows within 1.5 glyphs of the screen edges count as visible for smooth culling
+fn is_row_visible(char_y: f32, glyph_size: f32, height: f32) -> bool {
+    let margin = glyph_size * 1.5;
+    char_y >= -margin && char_y <= height + margin
 }
 
 // Regenerate character chain for recycled raindrops
-fn regenerate_chars(raindrop: &mut Raindrop, charset: &[char], rng: &mut rand::rngs::ThreadRng) {
+fn regenerate_chars(
+    raindrop: &mut Raindrop,
+    charset: &RainCharset,
+    styles: &DropStyles,
+    rng: &mut rand::rngs::ThreadRng,
+) {
+    raindrop.style = styles.pick(rng);
+    raindrop.seed = rng.gen();
+    raindrop.contact = Contact::Falling;
     raindrop.chars = [' '; 80];
-    raindrop.char_count = 0;
+    raindrop.transforms = [0; 80];
+    raindrop.fades = [None; 80];
     raindrop.last_midchain_frame = 0;
-    let new_length = rng.gen_range(42..70);
+    let new_length = raindrop.style.trail_length(rng.gen_range(42..70));
     raindrop.length = new_length;
-    
-    for _ in 0..new_length.min(80) {
-        let char_idx = rng.gen_range(0..charset.len());
-        raindrop.chars[raindrop.char_count] = charset[char_idx];
-        raindrop.char_count += 1;
-    }
+    raindrop.char_count = new_length.min(80);
+    raindrop.spelled = charset.fill_chain(
+        &mut raindrop.chars[..raindrop.char_count],
+        &mut raindrop.transforms[..raindrop.char_count],
+        rng,
+    );
+}
+
+// Start a drop over from the top of the virtual area, somewhere across the screen
+fn recycle(
+    raindrop: &mut Raindrop,
+    width: usize,
+    height: usize,
+    charset: &RainCharset,
+    styles: &DropStyles,
+    rng: &mut rand::rngs::ThreadRng,
+) {
+    raindrop.y = -(height as i32);
+    raindrop.x = rng.gen_range(0..width);
+    regenerate_chars(raindrop, charset, styles, rng);
+}
+
+// Swap the glyph at `index` for `glyph` (a character and its transform),
+// cross-fading from the old one
+fn mutate(
+    raindrop: &mut Raindrop,
+    index: usize,
+    glyph: (char, u32),
+    now: Instant,
+    crossfade: Duration,
+) {
+    let (old_ch, old_transform) = (raindrop.chars[index], raindrop.transforms[index]);
+    (raindrop.chars[index], raindrop.transforms[index]) = glyph;
+    raindrop.fades[index] = GlyphFade::start(old_ch, old_transform, now, crossfade);
 }
 
 impl RainSimulation {
-    pub fn new(width: usize, height: usize) -> Self {
+    pub fn new(
+        config: &RainConfig,
+        palette: Palette,
+        width: usize,
+        height: usize,
+        glyph_size: f32,
+        charset: RainCharset,
+    ) -> Self {
         let mut sim = Self {
             raindrops: Vec::new(),
             width,
             height,
+            glyph_size,
             virtual_height: height * 3,
             frame_count: 0,
             rng: rand::thread_rng(),
-            charset: get_charset(),
+            charset,
             last_animation_frame: 0,
+            trails: TrailDecay::new(config.trail_half_life),
+            crossfade: Duration::from_secs_f32(config.crossfade.max(0.0)),
+            now: Instant::now(),
+            styles: DropStyles::from_config(config, palette),
+            obstacles: Obstacles::from_config(&config.obstacles).map(Box::new),
+            splashes: Splashes::new(&config.splash),
         };
+        sim.fit_obstacles();
         sim.spawn_raindrops();
         sim
     }
 
+    // Color of the glyph at `index` in a drop's chain, in the drop's style
+    fn drop_color(&self, raindrop: &Raindrop, index: usize) -> [f32; 4] {
+        let column = (raindrop.x as f32 / self.glyph_size) as usize;
+        let lit = glitter_lit(raindrop.seed, self.frame_count);
+        self.styles.color(raindrop.style, index, raindrop.length, column, lit)
+    }
+
+    // Whether an obstacle hides the glyph of a drop drawn at char_y
+    fn hidden(&self, raindrop: &Raindrop, char_y: f32) -> bool {
+        self.obstacles
+            .as_ref()
+            .is_some_and(|obstacles| obstacles.hides(raindrop, char_y, self.glyph_size))
+    }
+
+    // Fit the obstacle mask to the screen's cells
+    fn fit_obstacles(&mut self) {
+        if let Some(obstacles) = &mut self.obstacles {
+            let (charset, rng) = (&self.charset, &mut self.rng);
+            obstacles.resize(self.width, self.height, self.glyph_size, || {
+                charset.pick(rng)
+            });
+        }
+    }
+
+    pub fn palette(&self) -> &Palette {
+        self.styles.palette()
+    }
+
+    pub fn palette_mut(&mut self) -> &mut Palette {
+        self.styles.palette_mut()
+    }
+
     fn spawn_raindrops(&mut self) {
         // Create initial raindrops across the width, starting above screen
-        for x in (0..self.width).step_by(40) {
+        let column_spacing = ((self.glyph_size * 1.25) as usize).max(1);
+        for x in (0..self.width).step_by(column_spacing) {
             self.create_raindrop(x);
         }
     }
 
     fn create_raindrop(&mut self, x: usize) {
-        let length = self.rng.gen_range(42..70);
+        let style = self.styles.pick(&mut self.rng);
+        let length = style.trail_length(self.rng.gen_range(42..70));
         
         // Weighted speed distribution: biased toward faster speeds
         // Sum of two ranges (2.0-4.0 + 0.0-1.0) = 2.0-5.0 with higher average
@@ -80,12 +203,13 @@ impl RainSimulation {
         let speed = base_speed + boost;
 
         let mut chars = [' '; 80];
-        let mut char_count = 0;
-        for _ in 0..length.min(80) {
-            let char_idx = self.rng.gen_range(0..self.charset.len());
-            chars[char_count] = self.charset[char_idx];
-            char_count += 1;
-        }
+        let mut transforms = [0; 80];
+        let char_count = length.min(80);
+        let spelled = self.charset.fill_chain(
+            &mut chars[..char_count],
+            &mut transforms[..char_count],
+            &mut self.rng,
+        );
 
         // Randomize spawn Y across entire virtual area (3x height)
         let random_spawn_offset = self.rng.gen_range(0..=(self.height as i32 * 3));
@@ -97,8 +221,14 @@ impl RainSimulation {
             length,
             speed,
             chars,
+            transforms,
+            fades: [None; 80],
             char_count,
             last_midchain_frame: 0,
+            spelled,
+            style,
+            seed: self.rng.gen(),
+            contact: Contact::Falling,
         });
     }
 
@@ -109,9 +239,9 @@ impl RainSimulation {
 
             // Update only the head glyph (position 0) for each raindrop
             for raindrop in &mut self.raindrops {
-                if raindrop.char_count > 0 {
-                    let char_idx = self.rng.gen_range(0..self.charset.len());
-                    raindrop.chars[0] = self.charset[char_idx];
+                if raindrop.char_count > 0 && !raindrop.spelled {
+                    let glyph = self.charset.pick(&mut self.rng);
+                    mutate(raindrop, 0, glyph, self.now, self.crossfade);
                 }
             }
         }
@@ -119,9 +249,14 @@ impl RainSimulation {
 
     fn animate_midchain(&mut self) {
         let height_f32 = self.height as f32;
+        let glyph_size = self.glyph_size;
         
         // Each raindrop independently animates its mid-chain glyphs 10 times per second
         for raindrop in &mut self.raindrops {
+            if raindrop.spelled {
+                continue;
+            }
+
             // Check if this raindrop's animation counter has reached 6 frames
             if self.frame_count - raindrop.last_midchain_frame >= 6 {
                 raindrop.last_midchain_frame = self.frame_count;
@@ -134,9 +269,9 @@ impl RainSimulation {
                 // Find all visible mid-chain positions (excluding head at position 0)
                 let mut visible_positions = Vec::new();
                 for char_idx in 1..raindrop.char_count {
-                    let char_y = raindrop.y as f32 - (char_idx as f32 * 32.0);
+                    let char_y = raindrop.y as f32 - (char_idx as f32 * glyph_size);
                     // Same visibility check as renderer
-                    if char_y >= -50.0 && char_y <= height_f32 + 50.0 {
+                    if is_row_visible(char_y, glyph_size, height_f32) {
                         visible_positions.push(char_idx);
                     }
                 }
@@ -145,8 +280,8 @@ impl RainSimulation {
                 if !visible_positions.is_empty() {
                     let pos_idx = self.rng.gen_range(0..visible_positions.len());
                     let char_pos = visible_positions[pos_idx];
-                    let char_idx = self.rng.gen_range(0..self.charset.len());
-                    raindrop.chars[char_pos] = self.charset[char_idx];
+                    let glyph = self.charset.pick(&mut self.rng);
+                    mutate(raindrop, char_pos, glyph, self.now, self.crossfade);
                 }
             }
         }
@@ -154,11 +289,11 @@ impl RainSimulation {
 
     pub fn update(&mut self) {
         self.frame_count = self.frame_count.wrapping_add(1);
+        self.now = Instant::now();
+        self.trails.begin_frame(self.now);
+        self.styles.palette_mut().update(self.now);
 
-        for raindrop in &mut self.raindrops {
-            // Direct pixel movement per frame, weighted toward faster speeds
-            raindrop.y += raindrop.speed as i32;
-        }
+        self.move_raindrops();
 
         // Animate glyphs (update head and mid-chain)
         self.animate_glyphs();
@@ -167,12 +302,99 @@ impl RainSimulation {
         // Recycle raindrops that exit bottom of screen (not removal)
         for raindrop in &mut self.raindrops {
             // Calculate tail position and recycle only when it exits bottom
-            let tail_y = raindrop.y - (raindrop.char_count as i32 * 32);
-            if tail_y > (self.height as i32 * 2) {
+            let tail_y = raindrop.y - (raindrop.char_count as f32 * self.glyph_size) as i32;
+            // Drops that have flowed into an obstacle entirely are done too
+            let tail_row = ((tail_y as f32 + self.glyph_size * 0.5) / self.glyph_size).floor();
+            let absorbed =
+                matches!(raindrop.contact, Contact::Absorbed(floor) if tail_row as i32 >= floor);
+            if tail_y > (self.height as i32 * 2) || absorbed {
                 // Recycle: reset to top of virtual area and randomize
-                raindrop.y = -(self.height as i32);
-                raindrop.x = self.rng.gen_range(0..self.width);
-                regenerate_chars(raindrop, &self.charset, &mut self.rng);
+                let (width, height) = (self.width, self.height);
+                recycle(raindrop, width, height, &self.charset, &self.styles, &mut self.rng);
+            }
+        }
+
+        self.touch_trails();
+    }
+
+    // Move each drop by its speed, stopping, piling or sliding drops at obstacles,
+    // and splash those whose head hits the bottom of the screen or an obstacle
+    fn move_raindrops(&mut self) {
+        if let Some(obstacles) = &mut self.obstacles {
+            obstacles.update(self.now);
+        }
+        self.splashes.update(self.now, self.glyph_size);
+
+        // Head position at which its cell touches the bottom of the screen
+        let bottom = self.height as i32 - self.glyph_size as i32;
+        // Drop index, the cell its head stopped in and any obstacle cell it landed in
+        let mut impacts = Vec::new();
+        for (index, raindrop) in self.raindrops.iter_mut().enumerate() {
+            let old_y = raindrop.y;
+            let step = match &self.obstacles {
+                Some(obstacles) => obstacles.advance(raindrop, self.glyph_size, self.width),
+                None => {
+                    // Direct pixel movement per frame, weighted toward faster speeds
+                    raindrop.y += raindrop.speed as i32;
+                    Step::Moved
+                }
+            };
+            match step {
+                Step::Moved => {
+                    let falling = matches!(raindrop.contact, Contact::Falling);
+                    if falling && old_y < bottom && raindrop.y >= bottom {
+                        impacts.push((index, (raindrop.x as f32, bottom as f32), None));
+                    }
+                }
+                Step::Landed(column, row) => {
+                    let cell = (column as f32 * self.glyph_size, row as f32 * self.glyph_size);
+                    impacts.push((index, cell, Some((column, row))));
+                }
+            }
+        }
+
+        for (index, position, landed) in impacts {
+            let raindrop = &self.raindrops[index];
+            let head_color = self.drop_color(raindrop, 0);
+            self.splashes.spawn(
+                position,
+                head_color,
+                self.glyph_size,
+                &self.charset,
+                &mut self.rng,
+            );
+
+            // Landing drops leave their head glyph on any pile, in their tail color
+            if let Some(cell) = landed {
+                let color = self.drop_color(raindrop, 1);
+                let glyph = (raindrop.chars[0], raindrop.transforms[0]);
+                if let Some(obstacles) = &mut self.obstacles {
+                    obstacles.land(cell, glyph, color, self.now);
+                }
+            }
+        }
+    }
+
+    // Leave each visible glyph in the cell under its center, to linger once the drop moves on
+    fn touch_trails(&mut self) {
+        let height_f32 = self.height as f32;
+        for raindrop in &self.raindrops {
+            for char_idx in 0..raindrop.char_count {
+                let char_y = raindrop.y as f32 - (char_idx as f32 * self.glyph_size);
+                if !is_row_visible(char_y, self.glyph_size, height_f32)
+                    || self.hidden(raindrop, char_y)
+                {
+                    continue;
+                }
+                let row = ((char_y + self.glyph_size * 0.5) / self.glyph_size).floor() as i32;
+                let color = self.drop_color(raindrop, char_idx);
+                self.trails.touch(
+                    raindrop.x,
+                    row,
+                    raindrop.chars[char_idx],
+                    raindrop.transforms[char_idx],
+                    color,
+                );
             }
         }
     }
@@ -182,136 +404,89 @@ impl RainSimulation {
         self.height = height;
         self.virtual_height = height * 3;
         self.raindrops.clear();
-        self.charset = get_charset();
+        self.trails.clear();
+        self.splashes.clear();
+        self.fit_obstacles();
         self.spawn_raindrops();
     }
 
-    pub fn generate_vertex_data(
-        &self,
-        glyph_map: &std::collections::HashMap<char, GlyphMetrics>,
-    ) -> (Vec<Vertex>, Vec<u32>) {
-        let mut vertices = Vec::new();
-        let mut indices = Vec::new();
+    // Change the glyph cell size (e.g. after a DPI change) and respawn the columns
+    pub fn set_glyph_size(&mut self, glyph_size: f32) {
+        if (glyph_size - self.glyph_size).abs() < f32::EPSILON {
+            return;
+        }
+        self.glyph_size = glyph_size;
+        self.raindrops.clear();
+        self.trails.clear();
+        self.splashes.clear();
+        self.fit_obstacles();
+        self.spawn_raindrops();
+    }
+
+    // Characters currently on screen, used to fill the on-demand glyph cache
+    pub fn visible_chars(&self) -> impl Iterator<Item = char> + '_ {
+        let height_f32 = self.height as f32;
+        let drops = self.raindrops.iter().flat_map(move |raindrop| {
+            raindrop.chars[..raindrop.char_count]
+                .iter()
+                .enumerate()
+                .filter(move |(char_idx, _)| {
+                    let char_y = raindrop.y as f32 - (*char_idx as f32 * self.glyph_size);
+                    is_row_visible(char_y, self.glyph_size, height_f32)
+                        && !self.hidden(raindrop, char_y)
+                })
+                .flat_map(move |(char_idx, &ch)| {
+                    // Glyphs fading out are drawn too
+                    let fading = raindrop.fades[char_idx]
+                        .and_then(|fade| fade.source(self.now, self.crossfade))
+                        .map(|(ch, _, _)| ch);
+                    std::iter::once(ch).chain(fading)
+                })
+        });
+        let obstacles = self.obstacles.iter().flat_map(|obstacles| obstacles.visible_chars());
+        drops
+            .chain(self.trails.visible_chars())
+            .chain(obstacles)
+            .chain(self.splashes.visible_chars())
+    }
 
-        let width_f32 = self.width as f32;
+    pub fn generate_vertex_data(&self, font_atlas: &FontAtlas) -> (Vec<Vertex>, Vec<u32>) {
+        let mut quads = GlyphQuads::new(font_atlas, self.width, self.height, self.glyph_size);
         let height_f32 = self.height as f32;
 
-        // Debug: count lookups and misses
-        let mut total_chars = 0;
-        let mut found_chars = 0;
-        let mut missed_chars = std::collections::HashSet::new();
+        // Fading glyphs first, so drops draw over them
+        self.trails.push_quads(&mut quads, self.glyph_size);
+        if let Some(obstacles) = &self.obstacles {
+            obstacles.push_quads(&mut quads, self.glyph_size, self.now);
+        }
 
         for raindrop in &self.raindrops {
             for (char_idx, &ch) in raindrop.chars[..raindrop.char_count].iter().enumerate() {
-                total_chars += 1;
-                
-                // Get glyph metrics
-                let glyph_metrics = match glyph_map.get(&ch) {
-                    Some(m) => {
-                        found_chars += 1;
-                        m
-                    }
-                    None => {
-                        missed_chars.insert(ch);
-                        continue; // Skip if glyph not available
-                    }
-                };
-
                 // Calculate Y position for this character
-                let char_y = raindrop.y as f32 - (char_idx as f32 * 32.0);
+                let char_y = raindrop.y as f32 - (char_idx as f32 * self.glyph_size);
 
-                // Skip if off-screen (with padding for smooth culling)
-                if char_y < -50.0 || char_y > height_f32 + 50.0 {
+                // Skip if off-screen (with padding for smooth culling) or inside an obstacle
+                if !is_row_visible(char_y, self.glyph_size, height_f32)
+                    || self.hidden(raindrop, char_y)
+                {
                     continue;
                 }
 
-                // Calculate color: white for head, fade to green for tail
-                let distance_from_head = char_idx as f32;
-                let max_distance = raindrop.length as f32;
-                let brightness = (1.0 - (distance_from_head / max_distance)) * 0.7 + 0.1;
-                let brightness = brightness.clamp(0.0, 1.0);
-
-                let color = if char_idx == 0 {
-                    // Head: pure white
-                    [1.0, 1.0, 1.0, 1.0]
-                } else {
-                    // Tail: green fade
-                    [
-                        brightness * 0.1,
-                        brightness * 1.0,
-                        brightness * 0.1,
-                        brightness,
-                    ]
-                };
-
-                // Convert pixel coords to NDC
-                let x_pixel = raindrop.x as f32;
-                let x_ndc = (2.0 * x_pixel / width_f32) - 1.0;
-                let y_ndc = 1.0 - (2.0 * char_y / height_f32);
-
-                // Glyph quad width and height in NDC
-                let glyph_width_ndc = (2.0 * glyph_metrics.width as f32) / width_f32;
-                let glyph_height_ndc = (2.0 * glyph_metrics.height as f32) / height_f32;
-
-                // Add quad vertices (2 triangles)
-                let base_idx = vertices.len() as u32;
-
-                // Bottom-left
-                vertices.push(Vertex {
-                    position: [x_ndc, y_ndc - glyph_height_ndc],
-                    uv: [glyph_metrics.u_min, glyph_metrics.v_max],
-                    color,
-                });
-
-                // Bottom-right
-                vertices.push(Vertex {
-                    position: [x_ndc + glyph_width_ndc, y_ndc - glyph_height_ndc],
-                    uv: [glyph_metrics.u_max, glyph_metrics.v_max],
-                    color,
-                });
-
-                // Top-left
-                vertices.push(Vertex {
-                    position: [x_ndc, y_ndc],
-                    uv: [glyph_metrics.u_min, glyph_metrics.v_min],
-                    color,
-                });
-
-                // Top-right
-                vertices.push(Vertex {
-                    position: [x_ndc + glyph_width_ndc, y_ndc],
-                    uv: [glyph_metrics.u_max, glyph_metrics.v_min],
-                    color,
-                });
-
-                // First triangle (bottom-left, bottom-right, top-left)
-                indices.push(base_idx);
-                indices.push(base_idx + 1);
-                indices.push(base_idx + 2);
-
-                // Second triangle (bottom-right, top-right, top-left)
-                indices.push(base_idx + 1);
-                indices.push(base_idx + 3);
-                indices.push(base_idx + 2);
+                let color = self.drop_color(raindrop, char_idx);
+                let transform = raindrop.transforms[char_idx];
+                let x = raindrop.x as f32;
+                let fade = raindrop.fades[char_idx]
+                    .and_then(|fade| fade.source(self.now, self.crossfade));
+                match fade {
+                    Some(from) => quads.push_fading(ch, transform, from, x, char_y, color),
+                    None => quads.push(ch, transform, x, char_y, color),
+                }
             }
         }
 
-        // Debug output: show lookup statistics
-        if total_chars > 0 {
-            eprintln!(
-                "[Vertex Gen] Total chars: {}, Found: {}, Missed: {} ({:.1}% hit rate)",
-                total_chars,
-                found_chars,
-                missed_chars.len(),
-                (found_chars as f32 / total_chars as f32) * 100.0
-            );
-            if !missed_chars.is_empty() {
-                let mut missed_list: Vec<char> = missed_chars.into_iter().collect();
-                missed_list.sort();
-                eprintln!("[Vertex Gen] Missing chars: {:?}", missed_list);
-            }
-        }
+        // Splashes fly over the rain
+        self.splashes.push_quads(&mut quads);
 
-        (vertices, indices)
+        quads.finish()
     }
 }
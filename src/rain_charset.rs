@@ -0,0 +1,245 @@
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::charsets;
+use crate::config::CharsetConfig;
+use crate::rain::get_charset;
+use crate::renderer::{VERTEX_FLIP_X, VERTEX_FLIP_Y, VERTEX_ROTATE_90};
+
+// Glyphs raindrops are drawn from, each with a relative weight and fixed
+// transform, plus an optional word list that drops spell out in reading order
+pub struct RainCharset {
+    chars: Vec<char>,
+    weights: Vec<f32>,
+    // VERTEX_FLIP_* / VERTEX_ROTATE_90 flags of each character
+    transforms: Vec<u32>,
+    distribution: WeightedIndex<f32>,
+    words: Vec<Vec<char>>,
+    words_chance: f64,
+    // Transforms a picked glyph may additionally get at random
+    random_transforms: Vec<u32>,
+    random_transform_chance: f64,
+}
+
+impl RainCharset {
+    // Every character equally likely
+    pub fn uniform(chars: Vec<char>) -> Self {
+        let weights = vec![1.0; chars.len()];
+        Self::weighted(chars, weights)
+    }
+
+    fn weighted(chars: Vec<char>, weights: Vec<f32>) -> Self {
+        let transforms = vec![0; chars.len()];
+        let distribution = WeightedIndex::new(&weights).unwrap_or_else(|_| {
+            // All weights zero: fall back to a uniform choice
+            WeightedIndex::new(vec![1.0; chars.len().max(1)]).unwrap()
+        });
+        Self {
+            chars,
+            weights,
+            transforms,
+            distribution,
+            words: Vec::new(),
+            words_chance: 0.0,
+            random_transforms: Vec::new(),
+            random_transform_chance: 0.0,
+        }
+    }
+
+    // Resolve the configured charset, falling back to the embedded atlas charset
+    pub fn from_config(config: &CharsetConfig) -> Self {
+        let mut charset =
+            if config.sets.is_empty() && config.chars.is_empty() && config.ranges.is_empty() {
+                Self::uniform(get_charset())
+            } else {
+                match Self::from_groups(config) {
+                    Ok(charset) if !charset.chars.is_empty() => charset,
+                    Ok(_) => {
+                        eprintln!("[Config] Charset is empty, using the embedded charset");
+                        Self::uniform(get_charset())
+                    }
+                    Err(e) => {
+                        eprintln!("[Config] {}, using the embedded charset", e);
+                        Self::uniform(get_charset())
+                    }
+                }
+            };
+
+        if let Some(path) = &config.words {
+            match load_words(path) {
+                Ok(words) => {
+                    eprintln!(
+                        "[Config] Loaded {} words from {}",
+                        words.len(),
+                        path.display()
+                    );
+                    charset.words = words;
+                    charset.words_chance = config.words_chance.clamp(0.0, 1.0);
+                }
+                Err(e) => eprintln!("[Config] {}, drops will not spell words", e),
+            }
+        }
+
+        if config.random_transform_chance > 0.0 {
+            // Each listed transform is picked on its own, e.g. one flip but never both
+            let random: Result<Vec<u32>, String> = config
+                .random_transforms
+                .iter()
+                .map(|name| parse_transform(name))
+                .collect();
+            match random {
+                Ok(transforms) => {
+                    charset.random_transforms = transforms;
+                    charset.random_transform_chance =
+                        config.random_transform_chance.clamp(0.0, 1.0);
+                }
+                Err(e) => eprintln!("[Config] {}, glyphs will not be transformed at random", e),
+            }
+        }
+        charset
+    }
+
+    // Each character takes the weight of the first group it appears in
+    fn from_groups(config: &CharsetConfig) -> Result<Self, String> {
+        let groups = charsets::groups(&config.sets, &config.chars, &config.ranges)?;
+        for name in config.weights.keys() {
+            if !groups.iter().any(|(group, _)| group == name) {
+                eprintln!("[Config] Charset weight for {:?} matches no group", name);
+            }
+        }
+        for name in config.transforms.keys() {
+            if !groups.iter().any(|(group, _)| group == name) {
+                eprintln!("[Config] Charset transforms for {:?} match no group", name);
+            }
+        }
+
+        let mut seen = HashSet::new();
+        let mut chars = Vec::new();
+        let mut weights = Vec::new();
+        let mut transforms = Vec::new();
+        for (name, group) in groups {
+            let weight = config.weights.get(&name).copied().unwrap_or(1.0).max(0.0);
+            let transform = match config.transforms.get(&name) {
+                Some(names) => parse_transforms(names)
+                    .map_err(|e| format!("Invalid transforms for {:?}: {}", name, e))?,
+                None => 0,
+            };
+            for ch in group {
+                if seen.insert(ch) {
+                    chars.push(ch);
+                    weights.push(weight);
+                    transforms.push(transform);
+                }
+            }
+        }
+        let mut charset = Self::weighted(chars, weights);
+        charset.transforms = transforms;
+        Ok(charset)
+    }
+
+    // Characters the atlas must hold: the charset plus every letter of the word list
+    pub fn atlas_chars(&self) -> Vec<char> {
+        let mut seen: HashSet<char> = self.chars.iter().copied().collect();
+        let mut chars = self.chars.clone();
+        for ch in self.words.iter().flatten() {
+            if seen.insert(*ch) {
+                chars.push(*ch);
+            }
+        }
+        chars
+    }
+
+    // Add characters with weight 1, e.g. sprite glyphs
+    pub fn extend(&mut self, chars: impl IntoIterator<Item = char>) {
+        for ch in chars {
+            if !self.chars.contains(&ch) {
+                self.chars.push(ch);
+                self.weights.push(1.0);
+                self.transforms.push(0);
+            }
+        }
+        if let Ok(distribution) = WeightedIndex::new(&self.weights) {
+            self.distribution = distribution;
+        }
+    }
+
+    // A random character and the transform it is drawn with
+    pub fn pick(&self, rng: &mut impl Rng) -> (char, u32) {
+        let index = self.distribution.sample(rng);
+        let mut transform = self.transforms[index];
+        if !self.random_transforms.is_empty() && rng.gen_bool(self.random_transform_chance) {
+            // Toggle, so a random flip can also undo a group's fixed one
+            transform ^= self.random_transforms[rng.gen_range(0..self.random_transforms.len())];
+        }
+        (self.chars[index], transform)
+    }
+
+    // Fill a drop's chain and glyph transforms, either with random glyphs or with
+    // consecutive words from the dictionary. Returns whether the chain spells words.
+    pub fn fill_chain(
+        &self,
+        chain: &mut [char],
+        transforms: &mut [u32],
+        rng: &mut impl Rng,
+    ) -> bool {
+        if self.words.is_empty() || !rng.gen_bool(self.words_chance) {
+            for (slot, transform) in chain.iter_mut().zip(transforms.iter_mut()) {
+                (*slot, *transform) = self.pick(rng);
+            }
+            return false;
+        }
+
+        // Words are always drawn upright
+        transforms.fill(0);
+
+        // Words continue from a random position in the dictionary, separated by
+        // blank cells. The head is the bottom of the chain, so text is written
+        // from the top (the end of the chain) down.
+        let start = rng.gen_range(0..self.words.len());
+        let text = self
+            .words
+            .iter()
+            .cycle()
+            .skip(start)
+            .flat_map(|word| word.iter().copied().chain(std::iter::once(' ')));
+        for (slot, ch) in chain.iter_mut().rev().zip(text) {
+            *slot = ch;
+        }
+        true
+    }
+}
+
+// Vertex flag of a transform name
+fn parse_transform(name: &str) -> Result<u32, String> {
+    match name {
+        "flip-x" => Ok(VERTEX_FLIP_X),
+        "flip-y" => Ok(VERTEX_FLIP_Y),
+        "rotate-90" => Ok(VERTEX_ROTATE_90),
+        _ => Err(format!(
+            "Unknown glyph transform {:?} (expected flip-x, flip-y or rotate-90)",
+            name
+        )),
+    }
+}
+
+// Combined vertex flags of a list of transform names
+fn parse_transforms(names: &[String]) -> Result<u32, String> {
+    names
+        .iter()
+        .try_fold(0, |flags, name| Ok(flags | parse_transform(name)?))
+}
+
+fn load_words(path: &Path) -> Result<Vec<Vec<char>>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let words: Vec<Vec<char>> = text
+        .split_whitespace()
+        .map(|word| word.chars().collect())
+        .collect();
+    if words.is_empty() {
+        return Err(format!("{} contains no words", path.display()));
+    }
+    Ok(words)
+}
@@ -1,10 +1,21 @@
+use ab_glyph::FontVec;
 use bytemuck::{Pod, Zeroable};
-use std::collections::HashMap;
+use image::RgbaImage;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use std::sync::Arc;
 use winit::window::Window;
 
+use crate::atlas_builder::{self, AtlasLayout};
+use crate::background::Background;
+use crate::bmfont;
+pub use crate::atlas_builder::GlyphMetrics;
+use crate::config::{BlendMode, Config, FontConfig};
 use crate::font_atlas::EmbeddedAtlas;
-use crate::rain::RainSimulation;
+use crate::glyph_cache::GlyphCache;
+use crate::layers::RainLayer;
+use crate::mipmap;
+use crate::sprites::{self, SpriteSheet};
 
 // GPU representation of a raindrop for compute shader
 #[repr(C)]
@@ -28,37 +39,63 @@ pub struct RainUniforms {
     pub _padding: u32,
 }
 
-#[derive(Copy, Clone, Debug)]
-pub struct GlyphMetrics {
-    pub u_min: f32,
-    pub v_min: f32,
-    pub u_max: f32,
-    pub v_max: f32,
-    pub width: u32,
-    pub height: u32,
-}
-
 pub struct FontAtlas {
-    #[allow(dead_code)]
     pub texture: wgpu::Texture,
     pub texture_view: wgpu::TextureView,
     pub glyph_map: HashMap<char, GlyphMetrics>,
-    #[allow(dead_code)]
+    // Glyph cell size the atlas was rasterized at, in texels
     pub font_size: u32,
+    // Distance from the top of a glyph cell to the baseline, in texels
+    pub ascent: f32,
     #[allow(dead_code)]
     pub atlas_width: u32,
     #[allow(dead_code)]
     pub atlas_height: u32,
+    // Number of texture array layers
+    #[allow(dead_code)]
+    pub page_count: u32,
+    // Mip levels per layer, limited by the padding between glyphs
+    mip_level_count: u32,
+    // Present when glyphs are rasterized on demand instead of pre-baked
+    cache: Option<GlyphCache>,
+    // Sprite glyphs, which the glyph cache must not replace
+    sprite_chars: HashSet<char>,
+    // Glyphs drawn in their own colors instead of the vertex tint
+    colored_glyphs: HashSet<char>,
 }
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct Vertex {
     pub position: [f32; 2],
-    pub uv: [f32; 2],
+    // Quad corner, from (0, 0) at the top-left to (1, 1) at the bottom-right
+    pub corner: [f32; 2],
     pub color: [f32; 4],
+    // Atlas texture array layer
+    pub layer: u32,
+    // VERTEX_* bit flags
+    pub flags: u32,
+    // Glyph rectangle in the atlas: u_min, v_min, u_max, v_max
+    pub uv_rect: [f32; 4],
+    // The glyph being cross-faded out, as corner, uv_rect, layer and flags above;
+    // the same glyph again when the cell is not fading
+    pub prev_corner: [f32; 2],
+    pub prev_uv_rect: [f32; 4],
+    pub prev_layer: u32,
+    pub prev_flags: u32,
+    // Share of the current glyph, from 0 (previous glyph only) to 1
+    pub blend: f32,
 }
 
+// Draw the texel colors instead of tinting the glyph with the vertex color
+pub const VERTEX_KEEP_COLORS: u32 = 1;
+// Glyph transforms, applied to the UVs in the vertex shader. The quad itself must
+// already be mirrored or rotated to match, since glyphs are rarely square.
+pub const VERTEX_FLIP_X: u32 = 2;
+pub const VERTEX_FLIP_Y: u32 = 4;
+// A quarter turn clockwise, applied after the flips
+pub const VERTEX_ROTATE_90: u32 = 8;
+
 impl Vertex {
     fn desc() -> wgpu::VertexBufferLayout<'static> {
         wgpu::VertexBufferLayout {
@@ -80,6 +117,46 @@ impl Vertex {
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 9]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 10]>() as wgpu::BufferAddress,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 14]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 20]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 21]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32,
+                },
             ],
         }
     }
@@ -87,78 +164,666 @@ impl Vertex {
 
 impl FontAtlas {
     pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
-        const ATLAS_WIDTH: u32 = 2048;
-        const ATLAS_HEIGHT: u32 = 2048;
-        const FONT_SIZE: u32 = 16;
-
         // Load embedded PNG atlas
         let embedded = EmbeddedAtlas::new();
-        
-        // Decode PNG to image buffer
-        let img = image::load_from_memory_with_format(
-            embedded.png_data,
-            image::ImageFormat::Png,
-        ).expect("Failed to decode embedded PNG atlas");
-        
-        let rgba_img = img.to_rgba8();
-        let atlas_data = rgba_img.to_vec();
+
+        // Decode each PNG page to an image buffer
+        let pages: Vec<RgbaImage> = embedded
+            .png_pages
+            .iter()
+            .map(|png_data| {
+                image::load_from_memory_with_format(png_data, image::ImageFormat::Png)
+                    .expect("Failed to decode embedded PNG atlas")
+                    .to_rgba8()
+            })
+            .collect();
 
         // Use the glyph map from the embedded atlas
         let glyph_map = embedded.glyph_coordinates;
 
         eprintln!(
-            "Font atlas loaded from PNG with {} glyphs",
+            "Font atlas loaded from {} PNG page(s) with {} glyphs",
+            pages.len(),
             glyph_map.len()
         );
 
-        // Create GPU texture
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Font Atlas Texture"),
-            size: wgpu::Extent3d {
-                width: ATLAS_WIDTH,
-                height: ATLAS_HEIGHT,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
+        Self::from_pages(
+            device,
+            queue,
+            &pages,
+            glyph_map,
+            embedded.glyph_size,
+            embedded.padding,
+            embedded.ascent,
+        )
+    }
 
-        // Write atlas data to texture
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &atlas_data,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(ATLAS_WIDTH * 4),
-                rows_per_image: Some(ATLAS_HEIGHT),
-            },
-            wgpu::Extent3d {
-                width: ATLAS_WIDTH,
-                height: ATLAS_HEIGHT,
-                depth_or_array_layers: 1,
-            },
+    // Rasterize a fallback chain of fonts into one atlas; each glyph comes from
+    // the first font that has it
+    pub fn from_fonts(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        fonts: &[FontVec],
+        charset: &[char],
+        glyph_size: u32,
+    ) -> Result<Self, String> {
+        let layout = AtlasLayout::new(glyph_size);
+        let atlas = atlas_builder::rasterize_atlas(fonts, charset, &layout)?;
+
+        eprintln!(
+            "Font atlas rasterized from {} font(s) with {} glyphs on {} page(s)",
+            fonts.len(),
+            atlas.glyph_map.len(),
+            atlas.pages.len()
+        );
+
+        Ok(Self::from_pages(
+            device,
+            queue,
+            &atlas.pages,
+            atlas.glyph_map,
+            glyph_size,
+            layout.padding,
+            atlas.ascent,
+        ))
+    }
+
+    // Load a pre-built atlas plus its page PNGs: a matrix-atlas metrics file, or a
+    // BMFont descriptor (text, XML or binary) when the extension is .fnt
+    pub fn from_metrics_file(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: &Path,
+    ) -> Result<Self, String> {
+        let data = std::fs::read(path)
+            .map_err(|e| format!("Failed to read atlas {}: {}", path.display(), e))?;
+        let metrics = if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("fnt")) {
+            bmfont::parse(&data)
+        } else {
+            String::from_utf8(data)
+                .map_err(|_| "Atlas is not valid UTF-8".to_string())
+                .and_then(|text| atlas_builder::parse_metrics(&text))
+        }
+        .map_err(|e| format!("Failed to parse atlas {}: {}", path.display(), e))?;
+
+        let layout = &metrics.layout;
+        if metrics.pages.len() > atlas_builder::MAX_PAGES {
+            return Err(format!(
+                "Atlas {} has {} pages, more than the limit of {}",
+                path.display(),
+                metrics.pages.len(),
+                atlas_builder::MAX_PAGES
+            ));
+        }
+        let max_size = device.limits().max_texture_dimension_2d;
+        if layout.width == 0 || layout.height == 0 || layout.width.max(layout.height) > max_size {
+            return Err(format!(
+                "Atlas {} is {}x{}, outside the 1-{} pixel texture limit",
+                path.display(),
+                layout.width,
+                layout.height,
+                max_size
+            ));
+        }
+        if let Some((ch, glyph)) =
+            metrics.glyph_map.iter().find(|(_, glyph)| glyph.layer as usize >= metrics.pages.len())
+        {
+            return Err(format!(
+                "Glyph U+{:04X} in atlas {} is on page {}, but there are only {} page(s)",
+                *ch as u32,
+                path.display(),
+                glyph.layer,
+                metrics.pages.len()
+            ));
+        }
+
+        // Page files are relative to the metrics file
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let pages = metrics
+            .pages
+            .iter()
+            .map(|file| {
+                image::open(dir.join(file))
+                    .map(coverage_to_rgba)
+                    .map_err(|e| format!("Failed to load atlas page {}: {}", file, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if pages.is_empty() {
+            return Err(format!("Atlas {} has no pages", path.display()));
+        }
+        for (file, page) in metrics.pages.iter().zip(&pages) {
+            if page.dimensions() != (layout.width, layout.height) {
+                return Err(format!(
+                    "Atlas page {} is {}x{}, but the atlas layout is {}x{}",
+                    file,
+                    page.width(),
+                    page.height(),
+                    layout.width,
+                    layout.height
+                ));
+            }
+        }
+
+        eprintln!(
+            "Font atlas loaded from {} with {} glyphs on {} page(s)",
+            path.display(),
+            metrics.glyph_map.len(),
+            pages.len()
+        );
+
+        Ok(Self::from_pages(
+            device,
+            queue,
+            &pages,
+            metrics.glyph_map,
+            metrics.layout.glyph_size,
+            metrics.layout.padding,
+            metrics.ascent,
+        ))
+    }
+
+    // Start with empty pages and rasterize glyphs the first time they are drawn
+    pub fn with_glyph_cache(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        fonts: Vec<FontVec>,
+        glyph_size: u32,
+        page_count: u32,
+    ) -> Result<Self, String> {
+        let layout = AtlasLayout::new(glyph_size);
+        layout.validate()?;
+        let mip_levels =
+            mipmap::level_count(layout.padding, glyph_size, layout.width, layout.height);
+        // The cache and the texture must agree on how many pages there are
+        let page_count = page_count.clamp(1, atlas_builder::MAX_PAGES as u32);
+        let cache = GlyphCache::new(fonts, layout, page_count, mip_levels);
+        let pages = vec![atlas_builder::new_page(&layout); page_count as usize];
+
+        eprintln!(
+            "Font atlas using an on-demand glyph cache with {} page(s)",
+            pages.len()
+        );
+
+        let mut font_atlas = Self::from_pages(
+            device,
+            queue,
+            &pages,
+            HashMap::new(),
+            glyph_size,
+            layout.padding,
+            cache.ascent(),
         );
+        font_atlas.cache = Some(cache);
+        Ok(font_atlas)
+    }
+
+    // Make sure the given characters are in the atlas before vertex generation;
+    // a no-op for pre-baked atlases
+    pub fn prepare(&mut self, queue: &wgpu::Queue, chars: impl IntoIterator<Item = char>) {
+        if let Some(cache) = &mut self.cache {
+            let sprite_chars = &self.sprite_chars;
+            let chars = chars.into_iter().filter(|ch| !sprite_chars.contains(ch));
+            cache.prepare(chars, &mut self.glyph_map, queue, &self.texture);
+        }
+    }
+
+    // Report charset characters that no font in the chain could provide
+    pub fn report_missing(&self, charset: &[char]) {
+        let missing: Vec<char> = charset
+            .iter()
+            .copied()
+            .filter(|ch| !self.sprite_chars.contains(ch))
+            .filter(|&ch| match &self.cache {
+                Some(cache) => !cache.is_covered(ch),
+                None => !self.glyph_map.contains_key(&ch),
+            })
+            .collect();
 
-        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        if !missing.is_empty() {
+            eprintln!("Font atlas has no glyph for {} characters:", missing.len());
+            for ch in &missing {
+                eprintln!("  - '{}' (U+{:04X})", ch, *ch as u32);
+            }
+        }
+    }
+
+    // Upload atlas pages as the layers of a 2D texture array, each with a mip chain
+    // as deep as the padding between glyphs allows
+    fn from_pages(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pages: &[RgbaImage],
+        glyph_map: HashMap<char, GlyphMetrics>,
+        font_size: u32,
+        padding: u32,
+        ascent: f32,
+    ) -> Self {
+        let (atlas_width, atlas_height) = pages[0].dimensions();
+        let page_count = pages.len() as u32;
+        let mip_level_count = mipmap::level_count(padding, font_size, atlas_width, atlas_height);
+
+        let texture =
+            create_atlas_texture(device, atlas_width, atlas_height, page_count, mip_level_count);
+        for (layer, page) in pages.iter().enumerate() {
+            upload_page(queue, &texture, layer as u32, page, &glyph_map, mip_level_count);
+        }
+        let texture_view = create_atlas_view(&texture);
 
         Self {
             texture,
             texture_view,
             glyph_map,
-            font_size: FONT_SIZE,
-            atlas_width: ATLAS_WIDTH,
-            atlas_height: ATLAS_HEIGHT,
+            font_size,
+            ascent,
+            atlas_width,
+            atlas_height,
+            page_count,
+            mip_level_count,
+            cache: None,
+            sprite_chars: HashSet::new(),
+            colored_glyphs: HashSet::new(),
+        }
+    }
+
+    // Whether the glyph is drawn in its own colors (see VERTEX_KEEP_COLORS)
+    pub fn keeps_colors(&self, ch: char) -> bool {
+        self.colored_glyphs.contains(&ch)
+    }
+
+    // Append sprite sheets to the atlas on pages of their own. The texture is
+    // recreated with the extra layers and the existing pages are copied over.
+    pub fn add_sprites(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        sheets: &[SpriteSheet],
+    ) {
+        // Sprite cells need at least as much padding as the mip chain assumes
+        let padding = atlas_builder::PADDING.max(1 << (self.mip_level_count - 1));
+        let layout = AtlasLayout {
+            width: self.atlas_width,
+            height: self.atlas_height,
+            glyph_size: self.font_size,
+            padding,
+        };
+        let (pages, glyph_map) =
+            sprites::pack_sprites(sheets, &layout, self.page_count, self.ascent);
+        if pages.is_empty() {
+            return;
+        }
+
+        let page_count = self.page_count + pages.len() as u32;
+        let texture = create_atlas_texture(
+            device,
+            self.atlas_width,
+            self.atlas_height,
+            page_count,
+            self.mip_level_count,
+        );
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Font Atlas Copy Encoder"),
+        });
+        for mip_level in 0..self.mip_level_count {
+            let level_copy = |texture| wgpu::ImageCopyTexture {
+                texture,
+                mip_level,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            };
+            encoder.copy_texture_to_texture(
+                level_copy(&self.texture),
+                level_copy(&texture),
+                wgpu::Extent3d {
+                    width: (self.atlas_width >> mip_level).max(1),
+                    height: (self.atlas_height >> mip_level).max(1),
+                    depth_or_array_layers: self.page_count,
+                },
+            );
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        for (i, page) in pages.iter().enumerate() {
+            let layer = self.page_count + i as u32;
+            upload_page(queue, &texture, layer, page, &glyph_map, self.mip_level_count);
+        }
+
+        for sheet in sheets {
+            let chars = sheet.sprites.iter().map(|sprite| sprite.ch);
+            self.sprite_chars.extend(chars.clone());
+            if sheet.keep_colors {
+                self.colored_glyphs.extend(chars);
+            }
+        }
+
+        eprintln!(
+            "Font atlas extended with {} sprites on {} page(s)",
+            glyph_map.len(),
+            pages.len()
+        );
+
+        self.glyph_map.extend(glyph_map);
+        self.texture_view = create_atlas_view(&texture);
+        self.texture = texture;
+        self.page_count = page_count;
+    }
+}
+
+fn create_atlas_texture(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    layers: u32,
+    mip_level_count: u32,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Font Atlas Texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: layers,
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        // COPY_SRC lets the atlas grow by copying into a texture with more layers
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_DST
+            | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+// Write a page and the mip levels generated from its glyphs to a texture array layer
+fn upload_page(
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    layer: u32,
+    page: &RgbaImage,
+    glyph_map: &HashMap<char, GlyphMetrics>,
+    mip_level_count: u32,
+) {
+    write_atlas_image(queue, texture, layer, 0, 0, 0, page);
+
+    let rects = mipmap::glyph_rects(glyph_map, layer, page.width(), page.height());
+    for (i, mip) in mipmap::generate_mips(page, &rects, mip_level_count).iter().enumerate() {
+        write_atlas_image(queue, texture, layer, i as u32 + 1, 0, 0, mip);
+    }
+}
+
+// Write an image to (x, y) of one mip level of a texture array layer
+pub fn write_atlas_image(
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    layer: u32,
+    mip_level: u32,
+    x: u32,
+    y: u32,
+    image: &RgbaImage,
+) {
+    let (width, height) = image.dimensions();
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level,
+            origin: wgpu::Origin3d { x, y, z: layer },
+            aspect: wgpu::TextureAspect::All,
+        },
+        image.as_raw(),
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(width * 4),
+            rows_per_image: Some(height),
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+}
+
+// Replace a buffer with one of at least `needed` bytes (rounded up to a power of
+// two) and the same usage; its contents are not kept
+fn grow_buffer(device: &wgpu::Device, buffer: &mut wgpu::Buffer, label: &str, needed: usize) {
+    let needed = needed as u64;
+    if needed <= buffer.size() {
+        return;
+    }
+    *buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: needed.next_power_of_two(),
+        usage: buffer.usage(),
+        mapped_at_creation: false,
+    });
+}
+
+fn create_atlas_view(texture: &wgpu::Texture) -> wgpu::TextureView {
+    // A single-layer texture would default to a plain 2D view
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        ..Default::default()
+    })
+}
+
+fn load_font(path: &Path) -> Result<FontVec, String> {
+    let font_data = std::fs::read(path)
+        .map_err(|e| format!("Failed to read font {}: {}", path.display(), e))?;
+    FontVec::try_from_vec(font_data)
+        .map_err(|e| format!("Failed to parse font {}: {}", path.display(), e))
+}
+
+// Load the configured font chain: the primary font (or the built-in one) followed
+// by the fallbacks. Fonts that fail to load are reported and skipped.
+fn load_font_chain(font_config: &FontConfig) -> Vec<FontVec> {
+    let mut fonts = Vec::new();
+
+    let primary = font_config.path.as_deref().and_then(|path| {
+        load_font(path)
+            .map_err(|e| eprintln!("{}, falling back to built-in font", e))
+            .ok()
+    });
+    match primary {
+        Some(font) => fonts.push(font),
+        None => {
+            let builtin = EmbeddedAtlas::new().font_data.to_vec();
+            fonts.push(FontVec::try_from_vec(builtin).expect("Failed to parse built-in font"));
+        }
+    }
+
+    for path in &font_config.fallbacks {
+        match load_font(path) {
+            Ok(font) => fonts.push(font),
+            Err(e) => eprintln!("{}, skipping fallback", e),
         }
     }
+
+    fonts
+}
+
+// Atlas pages from other tools are often opaque greyscale (white glyphs on black);
+// treat their luminance as coverage so the cell background stays transparent
+fn coverage_to_rgba(img: image::DynamicImage) -> RgbaImage {
+    if img.color().has_alpha() {
+        return img.to_rgba8();
+    }
+    let luma = img.to_luma8();
+    RgbaImage::from_fn(luma.width(), luma.height(), |x, y| {
+        image::Rgba([255, 255, 255, luma.get_pixel(x, y)[0]])
+    })
+}
+
+// The embedded atlas serves the config when no fonts are configured and the
+// charset needs nothing it lacks
+fn uses_embedded_atlas(font_config: &FontConfig, charset: &[char]) -> bool {
+    font_config.path.is_none()
+        && font_config.fallbacks.is_empty()
+        && !font_config.glyph_cache
+        && charset
+            .iter()
+            .all(|&ch| crate::font_atlas::CHARSET.contains(ch))
+}
+
+// Create the font atlas, preferring configured font files over the embedded atlas.
+// Runtime fonts are rasterized at physical pixel size so they stay crisp on HiDPI.
+fn load_font_atlas(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    font_config: &FontConfig,
+    charset: &[char],
+    sprite_sheets: &[SpriteSheet],
+    scale_factor: f64,
+) -> FontAtlas {
+    let glyph_size = (font_config.glyph_size as f64 * scale_factor).round() as u32;
+    let prebuilt = font_config.atlas.as_deref().and_then(|path| {
+        FontAtlas::from_metrics_file(device, queue, path)
+            .map_err(|e| eprintln!("{}, falling back to fonts", e))
+            .ok()
+    });
+
+    let built = if let Some(font_atlas) = prebuilt {
+        Ok(font_atlas)
+    } else if font_config.glyph_cache {
+        let fonts = load_font_chain(font_config);
+        FontAtlas::with_glyph_cache(device, queue, fonts, glyph_size, font_config.cache_pages)
+    } else if uses_embedded_atlas(font_config, charset) {
+        Ok(FontAtlas::new(device, queue))
+    } else {
+        let fonts = load_font_chain(font_config);
+        FontAtlas::from_fonts(device, queue, &fonts, charset, glyph_size)
+    };
+    // E.g. a glyph size that only outgrows the page after DPI scaling
+    let mut font_atlas = built.unwrap_or_else(|e| {
+        eprintln!("{}, falling back to the embedded atlas", e);
+        FontAtlas::new(device, queue)
+    });
+
+    // Sprites first, so their code points in the charset don't count as missing
+    font_atlas.add_sprites(device, queue, sprite_sheets);
+    font_atlas.report_missing(charset);
+    font_atlas
+}
+
+fn create_render_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    font_atlas: &FontAtlas,
+    sampler: &wgpu::Sampler,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Render Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&font_atlas.texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+        ],
+    })
+}
+
+// Every blend mode gets a pipeline, so layers can use any of them
+const BLEND_MODES: [BlendMode; 4] = [
+    BlendMode::Alpha,
+    BlendMode::Premultiplied,
+    BlendMode::Additive,
+    BlendMode::Screen,
+];
+
+// Blend state of the rain pipeline and the fragment entry point feeding it:
+// fs_main writes straight alpha, fs_premultiplied premultiplied colors
+fn blend_state(mode: BlendMode) -> (wgpu::BlendState, &'static str) {
+    let premultiplied = |color| wgpu::BlendState {
+        color,
+        alpha: wgpu::BlendComponent::OVER,
+    };
+    match mode {
+        BlendMode::Alpha => (
+            wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::Zero,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            },
+            "fs_main",
+        ),
+        BlendMode::Premultiplied => (
+            premultiplied(wgpu::BlendComponent::OVER),
+            "fs_premultiplied",
+        ),
+        BlendMode::Additive => (
+            premultiplied(wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            }),
+            "fs_premultiplied",
+        ),
+        // src + dst * (1 - src)
+        BlendMode::Screen => (
+            premultiplied(wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::OneMinusSrc,
+                operation: wgpu::BlendOperation::Add,
+            }),
+            "fs_premultiplied",
+        ),
+    }
+}
+
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    blend_mode: BlendMode,
+) -> wgpu::RenderPipeline {
+    let (blend, fragment_entry_point) = blend_state(blend_mode);
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(layout),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: fragment_entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(blend),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[Vertex::desc()],
+        },
+        multiview: None,
+    })
 }
 
 pub struct Renderer {
@@ -169,26 +834,32 @@ pub struct Renderer {
     queue: Arc<wgpu::Queue>,
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
-    render_pipeline: wgpu::RenderPipeline,
+    render_pipelines: HashMap<BlendMode, wgpu::RenderPipeline>,
     compute_pipeline: wgpu::ComputePipeline,
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
-    num_indices: u32,
     window: Arc<Window>,
     font_atlas: FontAtlas,
+    font_config: FontConfig,
+    // Characters the atlas must hold, without sprites
+    charset: Vec<char>,
+    sprite_sheets: Vec<SpriteSheet>,
+    render_bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
     #[allow(dead_code)]
     raindrops_buffer: wgpu::Buffer,
     #[allow(dead_code)]
     rain_uniforms_buffer: wgpu::Buffer,
     compute_bind_group: wgpu::BindGroup,
     render_bind_group: wgpu::BindGroup,
+    background: Option<Background>,
     frame_count: u32,
     #[allow(dead_code)]
     surface_needs_recreation: bool,
 }
 
 impl Renderer {
-    pub async fn new(window: Arc<Window>) -> Self {
+    pub async fn new(window: Arc<Window>, config: &Config, charset: &[char]) -> Self {
         let size = window.inner_size();
 
         // Create instance
@@ -229,11 +900,24 @@ impl Renderer {
         let queue = Arc::new(queue);
 
         // Create font atlas
-        let font_atlas = FontAtlas::new(&device, &queue);
+        let font_config = config.font.clone();
+        let charset = charset.to_vec();
+        let sprite_sheets = sprites::load_sprite_sheets(&config.sprites);
+        let font_atlas = load_font_atlas(
+            &device,
+            &queue,
+            &font_config,
+            &charset,
+            &sprite_sheets,
+            window.scale_factor(),
+        );
 
         // Get surface capabilities
         let capabilities = surface.get_capabilities(&adapter);
 
+        let background =
+            Background::load(&device, &queue, &config.background, capabilities.formats[0]);
+
         // Create surface config
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -295,7 +979,7 @@ impl Renderer {
                         visibility: wgpu::ShaderStages::FRAGMENT,
                         ty: wgpu::BindingType::Texture {
                             sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
                             multisampled: false,
                         },
                         count: None,
@@ -317,9 +1001,10 @@ impl Renderer {
             address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Linear,
             lod_min_clamp: 0.0,
-            lod_max_clamp: 1.0,
+            // The atlas texture limits the chain to the levels it actually has
+            lod_max_clamp: 32.0,
             compare: None,
             anisotropy_clamp: 1,
             border_color: None,
@@ -348,52 +1033,20 @@ impl Renderer {
             entry_point: "cs_update_rain",
         });
 
-        // Create render pipeline
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                unclipped_depth: false,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent {
-                            src_factor: wgpu::BlendFactor::SrcAlpha,
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                            operation: wgpu::BlendOperation::Add,
-                        },
-                        alpha: wgpu::BlendComponent {
-                            src_factor: wgpu::BlendFactor::One,
-                            dst_factor: wgpu::BlendFactor::Zero,
-                            operation: wgpu::BlendOperation::Add,
-                        },
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
-            },
-            multiview: None,
-        });
+        // Create render pipelines
+        let render_pipelines = BLEND_MODES
+            .iter()
+            .map(|&mode| {
+                let pipeline = create_render_pipeline(
+                    &device,
+                    &render_pipeline_layout,
+                    &shader,
+                    config.format,
+                    mode,
+                );
+                (mode, pipeline)
+            })
+            .collect();
 
         // Create buffers for rain simulation
         const MAX_RAINDROPS: usize = 1000;
@@ -428,22 +1081,11 @@ impl Renderer {
         });
 
         // Create render bind group
-        let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Render Bind Group"),
-            layout: &render_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&font_atlas.texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
-                },
-            ],
-        });
+        let render_bind_group =
+            create_render_bind_group(&device, &render_bind_group_layout, &font_atlas, &sampler);
 
-        // Create empty vertex and index buffers with COPY_DST for dynamic updates
+        // Create empty vertex and index buffers with COPY_DST for dynamic updates;
+        // render_frame grows them when a frame needs more
         const MAX_VERTICES: usize = 11520; // Max expected for 80 columns × 20 chars/drop × 6 vertices/quad
         const MAX_INDICES: usize = 17280; // Max expected indices for above
 
@@ -461,9 +1103,6 @@ impl Renderer {
             mapped_at_creation: false,
         });
 
-        // Start with 0 indices (will update each frame)
-        let num_indices = 0u32;
-
         Self {
             instance,
             adapter,
@@ -472,40 +1111,73 @@ impl Renderer {
             queue,
             config,
             size,
-            render_pipeline,
+            render_pipelines,
             compute_pipeline,
             vertex_buffer,
             index_buffer,
-            num_indices,
             window,
             font_atlas,
+            font_config,
+            charset,
+            sprite_sheets,
+            render_bind_group_layout,
+            sampler,
             raindrops_buffer,
             rain_uniforms_buffer,
             compute_bind_group,
             render_bind_group,
+            background,
             frame_count: 0,
             surface_needs_recreation: false,
         }
     }
 
-    pub fn render_frame(&mut self, rain: &RainSimulation) -> Result<(), wgpu::SurfaceError> {
+    // Draw the layers back to front
+    pub fn render_frame(&mut self, layers: &[RainLayer]) -> Result<(), wgpu::SurfaceError> {
         self.frame_count = self.frame_count.wrapping_add(1);
 
-        // Generate vertex data from rain simulation
-        let (vertices, indices) = rain.generate_vertex_data(&self.font_atlas.glyph_map);
+        // Rasterize any glyphs the visible rain needs, then generate vertex data
+        let visible_chars = layers
+            .iter()
+            .flat_map(|layer| layer.simulation.visible_chars());
+        self.font_atlas.prepare(&self.queue, visible_chars);
+
+        // All layers share the buffers; each draws its own index range
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut draws = Vec::new();
+        for layer in layers {
+            let (layer_vertices, layer_indices) =
+                layer.simulation.generate_vertex_data(&self.font_atlas);
+            let base_vertex = vertices.len() as i32;
+            let first_index = indices.len() as u32;
+            vertices.extend(layer_vertices);
+            indices.extend(layer_indices);
+            draws.push((layer.blend, first_index..indices.len() as u32, base_vertex));
+        }
+        // The bottom layer's theme sets the background
+        let background = layers
+            .first()
+            .map_or([0.0; 3], |layer| layer.simulation.background());
+
+        // Grow the buffers when the frame has more glyphs than they hold
+        let vertex_bytes: &[u8] = bytemuck::cast_slice(&vertices);
+        let index_bytes: &[u8] = bytemuck::cast_slice(&indices);
+        grow_buffer(&self.device, &mut self.vertex_buffer, "Vertex Buffer", vertex_bytes.len());
+        grow_buffer(&self.device, &mut self.index_buffer, "Index Buffer", index_bytes.len());
 
         // Write vertex data to GPU buffers
         if !vertices.is_empty() {
-            self.queue
-                .write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+            self.queue.write_buffer(&self.vertex_buffer, 0, vertex_bytes);
         }
 
         if !indices.is_empty() {
-            self.queue
-                .write_buffer(&self.index_buffer, 0, bytemuck::cast_slice(&indices));
+            self.queue.write_buffer(&self.index_buffer, 0, index_bytes);
         }
 
-        self.num_indices = indices.len() as u32;
+        if let Some(background) = &mut self.background {
+            background.update(&self.queue, self.config.width, self.config.height);
+        }
 
         // Try to get current texture, handling surface state changes
         let output = match self.surface.get_current_texture() {
@@ -549,7 +1221,7 @@ impl Renderer {
             compute_pass.set_pipeline(&self.compute_pipeline);
             compute_pass.set_bind_group(0, &self.compute_bind_group, &[]);
             // Dispatch with 256 threads per workgroup, assuming max 1000 raindrops
-            compute_pass.dispatch_workgroups((1000 + 255) / 256, 1, 1);
+            compute_pass.dispatch_workgroups(1000_u32.div_ceil(256), 1, 1);
         }
 
         // Render pass
@@ -561,9 +1233,9 @@ impl Renderer {
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
+                            r: background[0] as f64,
+                            g: background[1] as f64,
+                            b: background[2] as f64,
                             a: 1.0,
                         }),
                         store: wgpu::StoreOp::Store,
@@ -574,12 +1246,19 @@ impl Renderer {
                 timestamp_writes: None,
             });
 
-            render_pass.set_pipeline(&self.render_pipeline);
+            if let Some(background) = &self.background {
+                background.draw(&mut render_pass);
+            }
+
             render_pass.set_bind_group(0, &self.render_bind_group, &[]);
             render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
             render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-            if self.num_indices > 0 {
-                render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+            for (blend, range, base_vertex) in draws {
+                if range.is_empty() {
+                    continue;
+                }
+                render_pass.set_pipeline(&self.render_pipelines[&blend]);
+                render_pass.draw_indexed(range, base_vertex, 0..1);
             }
         }
 
@@ -628,6 +1307,36 @@ impl Renderer {
         }
     }
 
+    // Sprite glyphs that should fall in the rain alongside the font charset
+    pub fn sprite_charset(&self) -> Vec<char> {
+        self.sprite_sheets
+            .iter()
+            .filter(|sheet| sheet.in_charset)
+            .flat_map(|sheet| sheet.sprites.iter().map(|sprite| sprite.ch))
+            .collect()
+    }
+
+    pub fn on_scale_factor_changed(&mut self, scale_factor: f64) {
+        // The embedded atlas is fixed-size; only runtime fonts can be re-rasterized sharper
+        if uses_embedded_atlas(&self.font_config, &self.charset) {
+            return;
+        }
+        self.font_atlas = load_font_atlas(
+            &self.device,
+            &self.queue,
+            &self.font_config,
+            &self.charset,
+            &self.sprite_sheets,
+            scale_factor,
+        );
+        self.render_bind_group = create_render_bind_group(
+            &self.device,
+            &self.render_bind_group_layout,
+            &self.font_atlas,
+            &self.sampler,
+        );
+    }
+
     pub fn on_window_resized(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         self.size = new_size;
         // Recreate surface on resize to handle fullscreen transitions
@@ -0,0 +1,96 @@
+use std::time::Instant;
+
+use crate::config::{RainConfig, RainMode};
+use crate::grid::GridSimulation;
+use crate::rain::RainSimulation;
+use crate::rain_charset::RainCharset;
+use crate::renderer::{FontAtlas, Vertex};
+use crate::themes::{Palette, Theme};
+
+// The configured rain mode; every mode draws through the same renderer
+pub enum Simulation {
+    Falling(RainSimulation),
+    Grid(GridSimulation),
+}
+
+impl Simulation {
+    pub fn new(
+        config: &RainConfig,
+        palette: Palette,
+        width: usize,
+        height: usize,
+        glyph_size: f32,
+        charset: RainCharset,
+    ) -> Self {
+        match config.mode {
+            RainMode::Falling => Simulation::Falling(RainSimulation::new(
+                config, palette, width, height, glyph_size, charset,
+            )),
+            RainMode::Grid => {
+                // Both need drops that fall into something
+                if config.obstacles.image.is_some() || config.obstacles.text.is_some() {
+                    eprintln!("[Config] Obstacles only work in falling mode, ignoring them");
+                }
+                if config.splash.count > 0 {
+                    eprintln!("[Config] Splashes only work in falling mode, ignoring them");
+                }
+                Simulation::Grid(GridSimulation::new(
+                    config, palette, width, height, glyph_size, charset,
+                ))
+            }
+        }
+    }
+
+    // Fade into `theme`, e.g. when the user cycles themes
+    pub fn set_theme(&mut self, theme: Theme) {
+        let palette = match self {
+            Simulation::Falling(rain) => rain.palette_mut(),
+            Simulation::Grid(grid) => grid.palette_mut(),
+        };
+        palette.set_theme(theme, Instant::now());
+    }
+
+    // Background color of the current theme
+    pub fn background(&self) -> [f32; 3] {
+        match self {
+            Simulation::Falling(rain) => rain.palette().background(),
+            Simulation::Grid(grid) => grid.palette().background(),
+        }
+    }
+
+    pub fn update(&mut self) {
+        match self {
+            Simulation::Falling(rain) => rain.update(),
+            Simulation::Grid(grid) => grid.update(),
+        }
+    }
+
+    pub fn resize(&mut self, width: usize, height: usize) {
+        match self {
+            Simulation::Falling(rain) => rain.resize(width, height),
+            Simulation::Grid(grid) => grid.resize(width, height),
+        }
+    }
+
+    pub fn set_glyph_size(&mut self, glyph_size: f32) {
+        match self {
+            Simulation::Falling(rain) => rain.set_glyph_size(glyph_size),
+            Simulation::Grid(grid) => grid.set_glyph_size(glyph_size),
+        }
+    }
+
+    // Characters currently on screen, used to fill the on-demand glyph cache
+    pub fn visible_chars(&self) -> Box<dyn Iterator<Item = char> + '_> {
+        match self {
+            Simulation::Falling(rain) => Box::new(rain.visible_chars()),
+            Simulation::Grid(grid) => Box::new(grid.visible_chars()),
+        }
+    }
+
+    pub fn generate_vertex_data(&self, font_atlas: &FontAtlas) -> (Vec<Vertex>, Vec<u32>) {
+        match self {
+            Simulation::Falling(rain) => rain.generate_vertex_data(font_atlas),
+            Simulation::Grid(grid) => grid.generate_vertex_data(font_atlas),
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use rand::Rng;
+use std::f32::consts::PI;
+use std::time::{Duration, Instant};
+
+use crate::config::SplashConfig;
+use crate::glyph_quads::GlyphQuads;
+use crate::rain_charset::RainCharset;
+
+// Oldest particles make way beyond this many
+const MAX_PARTICLES: usize = 4096;
+// Splashes scatter upwards, up to this angle either side of straight up
+const SPREAD: f32 = PI * 0.4;
+
+// A glyph flying off an impact; positions and velocities are in pixels
+struct Particle {
+    ch: char,
+    transform: u32,
+    x: f32,
+    y: f32,
+    velocity: [f32; 2],
+    color: [f32; 4],
+    born: Instant,
+}
+
+// Short-lived glyphs scattered by drops hitting the bottom of the screen or an obstacle
+pub struct Splashes {
+    particles: Vec<Particle>,
+    count: usize,
+    lifetime: Duration,
+    // Glyphs per second, and per second squared
+    speed: f32,
+    gravity: f32,
+    // Time of the current frame
+    now: Instant,
+}
+
+impl Splashes {
+    pub fn new(config: &SplashConfig) -> Self {
+        Self {
+            particles: Vec::new(),
+            count: config.count,
+            lifetime: Duration::from_secs_f32(config.lifetime.max(0.0)),
+            speed: config.speed,
+            gravity: config.gravity,
+            now: Instant::now(),
+        }
+    }
+
+    // Scatter particles from the cell whose top-left corner is at `cell`, in
+    // glyphs picked from the charset
+    pub fn spawn(
+        &mut self,
+        cell: (f32, f32),
+        color: [f32; 4],
+        glyph_size: f32,
+        charset: &RainCharset,
+        rng: &mut impl Rng,
+    ) {
+        if self.lifetime.is_zero() {
+            return;
+        }
+        for _ in 0..self.count {
+            let angle = rng.gen_range(-SPREAD..=SPREAD);
+            let speed = self.speed * glyph_size * rng.gen_range(0.5..=1.0);
+            let (ch, transform) = charset.pick(rng);
+            self.particles.push(Particle {
+                ch,
+                transform,
+                x: cell.0,
+                y: cell.1,
+                velocity: [angle.sin() * speed, -angle.cos() * speed],
+                color,
+                born: self.now,
+            });
+        }
+        let excess = self.particles.len().saturating_sub(MAX_PARTICLES);
+        self.particles.drain(..excess);
+    }
+
+    // Move the particles to `now` and forget those that have faded out
+    pub fn update(&mut self, now: Instant, glyph_size: f32) {
+        let elapsed = now.duration_since(self.now).as_secs_f32();
+        self.now = now;
+        let lifetime = self.lifetime;
+        self.particles
+            .retain(|particle| now.duration_since(particle.born) < lifetime);
+
+        let gravity = self.gravity * glyph_size;
+        for particle in &mut self.particles {
+            particle.velocity[1] += gravity * elapsed;
+            particle.x += particle.velocity[0] * elapsed;
+            particle.y += particle.velocity[1] * elapsed;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+    }
+
+    // Characters in flight, used to fill the on-demand glyph cache
+    pub fn visible_chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.particles.iter().map(|particle| particle.ch)
+    }
+
+    // Draw the particles, fading out over their lifetime
+    pub fn push_quads(&self, quads: &mut GlyphQuads) {
+        let lifetime = self.lifetime.as_secs_f32().max(f32::EPSILON);
+        for particle in &self.particles {
+            let age = self.now.duration_since(particle.born).as_secs_f32() / lifetime;
+            let color = particle.color.map(|channel| channel * (1.0 - age).max(0.0));
+            quads.push(
+                particle.ch,
+                particle.transform,
+                particle.x,
+                particle.y,
+                color,
+            );
+        }
+    }
+}
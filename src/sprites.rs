@@ -0,0 +1,233 @@
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::atlas_builder::{self, AtlasLayout, GlyphMetrics, RasterizedGlyph};
+use crate::config::SpriteSheetConfig;
+
+// Sprites are mapped into the Private Use Area unless a sheet says otherwise
+const PRIVATE_USE_START: u32 = 0xE000;
+
+pub struct Sprite {
+    pub ch: char,
+    pub image: RgbaImage,
+}
+
+// The frames of one sprite sheet, already mapped to their code points
+pub struct SpriteSheet {
+    pub sprites: Vec<Sprite>,
+    pub keep_colors: bool,
+    pub in_charset: bool,
+}
+
+// Frame rectangle in TexturePacker's JSON layout
+#[derive(Deserialize)]
+struct FrameRect {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+#[derive(Deserialize)]
+struct FrameEntry {
+    frame: FrameRect,
+}
+
+// TexturePacker writes frames either as a name -> frame object or as an array
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Frames {
+    Hash(BTreeMap<String, FrameEntry>),
+    Array(Vec<FrameEntry>),
+}
+
+#[derive(Deserialize)]
+struct FrameFile {
+    frames: Frames,
+}
+
+// Load every configured sprite sheet. Sheets that fail to load are reported and skipped.
+pub fn load_sprite_sheets(configs: &[SpriteSheetConfig]) -> Vec<SpriteSheet> {
+    let mut next_code = PRIVATE_USE_START;
+    let mut sheets = Vec::new();
+
+    for config in configs {
+        match load_sprite_sheet(config, next_code) {
+            Ok(sheet) => {
+                if let (Some(first), Some(last)) = (sheet.sprites.first(), sheet.sprites.last()) {
+                    eprintln!(
+                        "[Sprites] Loaded {} sprites from {} as U+{:04X}..U+{:04X}",
+                        sheet.sprites.len(),
+                        config.image.display(),
+                        first.ch as u32,
+                        last.ch as u32
+                    );
+                    next_code = last.ch as u32 + 1;
+                }
+                sheets.push(sheet);
+            }
+            Err(e) => eprintln!("[Sprites] {}, skipping sheet", e),
+        }
+    }
+    sheets
+}
+
+fn load_sprite_sheet(config: &SpriteSheetConfig, default_code: u32) -> Result<SpriteSheet, String> {
+    let sheet = image::open(&config.image)
+        .map_err(|e| format!("Failed to load {}: {}", config.image.display(), e))?
+        .to_rgba8();
+
+    let first_code = match &config.first_char {
+        Some(spec) => {
+            let chars = atlas_builder::parse_ranges(&[spec])?;
+            let first = chars
+                .first()
+                .ok_or_else(|| format!("first_char {:?} is not a valid character", spec))?;
+            *first as u32
+        }
+        None => default_code,
+    };
+
+    let rects = match &config.frames {
+        Some(path) => load_frames(path)?,
+        None => grid_frames(config, sheet.width(), sheet.height())?,
+    };
+
+    let sprites = rects
+        .into_iter()
+        .enumerate()
+        .map(|(i, rect)| {
+            let ch = char::from_u32(first_code + i as u32).ok_or_else(|| {
+                format!(
+                    "No code point for sprite {} of {}",
+                    i,
+                    config.image.display()
+                )
+            })?;
+            let outside = |start: u32, len: u32, size: u32| {
+                start.checked_add(len).is_none_or(|end| end > size)
+            };
+            if outside(rect.x, rect.w, sheet.width()) || outside(rect.y, rect.h, sheet.height()) {
+                return Err(format!(
+                    "Frame {} lies outside {}",
+                    i,
+                    config.image.display()
+                ));
+            }
+            let image = imageops::crop_imm(&sheet, rect.x, rect.y, rect.w, rect.h).to_image();
+            Ok(Sprite { ch, image })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    Ok(SpriteSheet {
+        sprites,
+        keep_colors: config.keep_colors,
+        in_charset: config.in_charset,
+    })
+}
+
+fn load_frames(path: &Path) -> Result<Vec<FrameRect>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let file: FrameFile = serde_json::from_str(&text)
+        .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+
+    // Hash frames come back sorted by name, so the code point order is stable
+    let entries = match file.frames {
+        Frames::Hash(frames) => frames.into_values().collect(),
+        Frames::Array(frames) => frames,
+    };
+    Ok(entries.into_iter().map(|entry| entry.frame).collect())
+}
+
+fn grid_frames(
+    config: &SpriteSheetConfig,
+    width: u32,
+    height: u32,
+) -> Result<Vec<FrameRect>, String> {
+    let (w, h) = (config.cell_width, config.cell_height);
+    if w == 0 || h == 0 {
+        return Err(format!(
+            "Sprite cells of {} must not be empty",
+            config.image.display()
+        ));
+    }
+
+    let columns = width / w;
+    let cells = columns * (height / h);
+    let count = config.count.map_or(cells, |count| count.min(cells));
+    Ok((0..count)
+        .map(|i| FrameRect {
+            x: (i % columns) * w,
+            y: (i / columns) * h,
+            w,
+            h,
+        })
+        .collect())
+}
+
+// Scale a sprite to fit a glyph cell, keeping its aspect ratio. Upscaling uses
+// nearest-neighbour filtering so pixel art stays crisp.
+fn fit_to_cell(image: &RgbaImage, cell: u32) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let scale = cell as f32 / width.max(height) as f32;
+    let fitted_width = ((width as f32 * scale).round() as u32).clamp(1, cell);
+    let fitted_height = ((height as f32 * scale).round() as u32).clamp(1, cell);
+    let filter = if scale > 1.0 {
+        FilterType::Nearest
+    } else {
+        FilterType::Triangle
+    };
+    imageops::resize(image, fitted_width, fitted_height, filter)
+}
+
+// Pack sprites into new atlas pages starting at texture array layer `first_layer`.
+// Each sprite is centered in the glyph cell, whose baseline lies `ascent` texels
+// below its top.
+pub fn pack_sprites(
+    sheets: &[SpriteSheet],
+    layout: &AtlasLayout,
+    first_layer: u32,
+    ascent: f32,
+) -> (Vec<RgbaImage>, HashMap<char, GlyphMetrics>) {
+    let mut pages = Vec::new();
+    let mut glyph_map = HashMap::new();
+    let mut cells = Vec::new().into_iter();
+
+    for sprite in sheets.iter().flat_map(|sheet| &sheet.sprites) {
+        let (x, y) = match cells.next() {
+            Some(cell) => cell,
+            None => {
+                if first_layer as usize + pages.len() == atlas_builder::MAX_PAGES {
+                    eprintln!("[Sprites] Atlas full, skipping remaining sprites");
+                    break;
+                }
+                pages.push(atlas_builder::new_page(layout));
+                cells = atlas_builder::cell_origins(layout)
+                    .collect::<Vec<_>>()
+                    .into_iter();
+                cells.next().expect("Glyph size larger than an atlas page")
+            }
+        };
+
+        let cell = layout.glyph_size;
+        let bitmap = fit_to_cell(&sprite.image, cell);
+        let (width, height) = bitmap.dimensions();
+        let glyph = RasterizedGlyph {
+            bearing_x: (cell - width) as f32 * 0.5,
+            bearing_y: (cell - height) as f32 * 0.5 - ascent,
+            advance: cell as f32,
+            bitmap,
+        };
+
+        let layer = pages.len() - 1;
+        imageops::replace(&mut pages[layer], &glyph.bitmap, x as i64, y as i64);
+        let metrics = atlas_builder::place_glyph(&glyph, layout, first_layer + layer as u32, x, y);
+        glyph_map.insert(sprite.ch, metrics);
+    }
+
+    (pages, glyph_map)
+}
@@ -0,0 +1,165 @@
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+
+use crate::config::RainConfig;
+use crate::falloff::Falloff;
+use crate::themes::{Palette, ThemePart};
+
+// Brightness of dim drops relative to normal ones
+const DIM_BRIGHTNESS: f32 = 0.45;
+// Dim drops are this many times shorter than normal ones
+const DIM_SHORTENING: usize = 3;
+// Head brightness of double-head drops relative to normal heads. Past 1.0 the
+// head glows under the additive and screen blend modes.
+const DOUBLE_HEAD_BRIGHTNESS: f32 = 2.0;
+// Frames a glitter head stays lit or dark before it may flicker again
+const GLITTER_FRAMES: u32 = 3;
+
+// How a drop is colored, picked when it spawns or recycles
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropStyle {
+    // Bright head over the tail gradient
+    Normal,
+    // No bright head, only the tail
+    Headless,
+    // A head twice as bright as a normal one
+    DoubleHead,
+    // Bright head that flickers on and off
+    Glitter,
+    // A faint, short drop without a bright head
+    Dim,
+    // Bright head over a tail in the accent color
+    Accent,
+}
+
+impl DropStyle {
+    const ALL: [(&'static str, DropStyle); 6] = [
+        ("normal", DropStyle::Normal),
+        ("headless", DropStyle::Headless),
+        ("double-head", DropStyle::DoubleHead),
+        ("glitter", DropStyle::Glitter),
+        ("dim", DropStyle::Dim),
+        ("accent", DropStyle::Accent),
+    ];
+
+    // Trail length of a drop in this style, from the length a normal drop would have
+    pub fn trail_length(self, length: usize) -> usize {
+        match self {
+            DropStyle::Dim => (length / DIM_SHORTENING).max(1),
+            _ => length,
+        }
+    }
+
+    fn parse(name: &str) -> Result<Self, String> {
+        Self::ALL
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, style)| *style)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Self::ALL.iter().map(|(known, _)| *known).collect();
+                format!(
+                    "Unknown drop style {:?} (expected one of {})",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+// The configured mix of drop styles and the colors they are drawn in
+pub struct DropStyles {
+    styles: Vec<DropStyle>,
+    distribution: WeightedIndex<f32>,
+    // Overrides the theme's accent color
+    accent: Option<[f32; 3]>,
+    falloff: Falloff,
+    palette: Palette,
+}
+
+impl DropStyles {
+    pub fn from_config(config: &RainConfig, palette: Palette) -> Self {
+        let (styles, distribution) = weighted_styles(config).unwrap_or_else(|e| {
+            eprintln!("[Config] {}, all drops use the normal style", e);
+            (vec![DropStyle::Normal], WeightedIndex::new([1.0]).unwrap())
+        });
+        Self {
+            styles,
+            distribution,
+            accent: config.accent_color,
+            falloff: Falloff::from_config(&config.falloff),
+            palette,
+        }
+    }
+
+    pub fn pick(&self, rng: &mut impl Rng) -> DropStyle {
+        self.styles[self.distribution.sample(rng)]
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn palette_mut(&mut self) -> &mut Palette {
+        &mut self.palette
+    }
+
+    // Color of the glyph `distance` cells behind the head of a drop `length`
+    // cells long in `column`; `lit` is whether a glitter head is currently on
+    pub fn color(
+        &self,
+        style: DropStyle,
+        distance: usize,
+        length: usize,
+        column: usize,
+        lit: bool,
+    ) -> [f32; 4] {
+        let head = self.falloff.head();
+        let brightness = self.falloff.trail(distance, length);
+        let tail = ThemePart::Tail(distance as f32 / length.max(1) as f32);
+        let (part, brightness) = match style {
+            DropStyle::Normal | DropStyle::Accent if distance == 0 => (ThemePart::Head, head),
+            DropStyle::DoubleHead if distance == 0 => {
+                (ThemePart::Head, head * DOUBLE_HEAD_BRIGHTNESS)
+            }
+            DropStyle::Glitter if distance == 0 && lit => (ThemePart::Head, head),
+            DropStyle::Accent => (ThemePart::Accent, brightness),
+            DropStyle::Dim => (tail, brightness * DIM_BRIGHTNESS),
+            _ => (tail, brightness),
+        };
+        let color = match (part, self.accent) {
+            (ThemePart::Accent, Some(accent)) => accent,
+            _ => self.palette.color(part, column),
+        };
+        tint(color, brightness)
+    }
+}
+
+fn weighted_styles(config: &RainConfig) -> Result<(Vec<DropStyle>, WeightedIndex<f32>), String> {
+    let mut styles = Vec::new();
+    let mut weights = Vec::new();
+    for (name, weight) in &config.styles {
+        styles.push(DropStyle::parse(name)?);
+        weights.push(weight.max(0.0));
+    }
+    let distribution = WeightedIndex::new(&weights)
+        .map_err(|_| "Drop style weights must include a positive weight".to_string())?;
+    Ok((styles, distribution))
+}
+
+// Whether a glitter head with this seed is lit on `frame`
+pub fn glitter_lit(seed: u32, frame: u32) -> bool {
+    let mut hash = seed
+        .wrapping_add(frame / GLITTER_FRAMES)
+        .wrapping_mul(0x9E37_79B1);
+    hash ^= hash >> 15;
+    hash & 1 == 0
+}
+
+fn tint(color: [f32; 3], brightness: f32) -> [f32; 4] {
+    [
+        color[0] * brightness,
+        color[1] * brightness,
+        color[2] * brightness,
+        brightness,
+    ]
+}
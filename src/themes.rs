@@ -0,0 +1,289 @@
+use serde::Deserialize;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// Hue step between neighbouring columns of rainbow themes; the spectrum
+// repeats every 1 / RAINBOW_HUE_STEP columns
+const RAINBOW_HUE_STEP: f32 = 0.05;
+
+// Tail stops of the colors captured when a theme switch interrupts a fade
+const SNAPSHOT_TAIL_STOPS: usize = 16;
+
+// Colors the rain is drawn in. Theme files are TOML tables with these keys.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Theme {
+    pub head: [f32; 3],
+    // Tail gradient stops, evenly spaced from just behind the head to the end of the tail
+    pub tail: Vec<[f32; 3]>,
+    pub background: [f32; 3],
+    // Tail color of accent drops
+    pub accent: [f32; 3],
+    // Give each column its own hue instead of the tail gradient
+    pub rainbow: bool,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            head: [1.0, 1.0, 1.0],
+            tail: vec![[0.1, 1.0, 0.1]],
+            background: [0.0, 0.0, 0.0],
+            accent: [0.3, 0.8, 1.0],
+            rainbow: false,
+        }
+    }
+}
+
+// Names of the built-in themes, in cycling order
+pub const BUILTIN_THEMES: &[&str] = &[
+    "classic",
+    "amber",
+    "resurrections",
+    "red-alert",
+    "monochrome",
+    "rainbow",
+];
+
+// A built-in theme by name
+pub fn builtin(name: &str) -> Option<Theme> {
+    let classic = Theme::default();
+    let theme = match name {
+        "classic" => classic,
+        // Phosphor of an amber terminal
+        "amber" => Theme {
+            head: [1.0, 0.92, 0.7],
+            tail: vec![[1.0, 0.65, 0.1], [0.8, 0.35, 0.0]],
+            background: [0.02, 0.01, 0.0],
+            accent: [1.0, 0.9, 0.5],
+            ..classic
+        },
+        // The blue-tinted code of Matrix Resurrections
+        "resurrections" => Theme {
+            head: [0.85, 0.95, 1.0],
+            tail: vec![[0.3, 0.75, 1.0], [0.1, 0.35, 0.9]],
+            background: [0.0, 0.01, 0.03],
+            accent: [0.7, 1.0, 0.9],
+            ..classic
+        },
+        "red-alert" => Theme {
+            head: [1.0, 0.85, 0.85],
+            tail: vec![[1.0, 0.15, 0.1], [0.6, 0.0, 0.05]],
+            background: [0.03, 0.0, 0.0],
+            accent: [1.0, 0.7, 0.1],
+            ..classic
+        },
+        "monochrome" => Theme {
+            head: [1.0, 1.0, 1.0],
+            tail: vec![[0.8, 0.8, 0.8], [0.45, 0.45, 0.45]],
+            accent: [1.0, 1.0, 1.0],
+            ..classic
+        },
+        "rainbow" => Theme {
+            rainbow: true,
+            ..classic
+        },
+        _ => return None,
+    };
+    Some(theme)
+}
+
+// A built-in theme name, or the path of a theme file
+pub fn load(spec: &str) -> Result<Theme, String> {
+    if let Some(theme) = builtin(spec) {
+        return Ok(theme);
+    }
+    let path = Path::new(spec);
+    if !path.exists() {
+        return Err(format!(
+            "Unknown theme {:?} (expected a theme file or one of {})",
+            spec,
+            BUILTIN_THEMES.join(", ")
+        ));
+    }
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let theme: Theme =
+        toml::from_str(&text).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+    if theme.tail.is_empty() {
+        return Err(format!("{} has no tail colors", path.display()));
+    }
+    Ok(theme)
+}
+
+// Which color of a theme a glyph is drawn in
+#[derive(Clone, Copy, Debug)]
+pub enum ThemePart {
+    Head,
+    // Position along the tail, from 0 behind the head to 1 at the end
+    Tail(f32),
+    Accent,
+}
+
+impl Theme {
+    fn color(&self, part: ThemePart, column: usize) -> [f32; 3] {
+        match part {
+            ThemePart::Head => self.head,
+            ThemePart::Accent => self.accent,
+            ThemePart::Tail(_) if self.rainbow => {
+                hue_color((column as f32 * RAINBOW_HUE_STEP).fract())
+            }
+            ThemePart::Tail(t) => gradient(&self.tail, t),
+        }
+    }
+}
+
+// Colors on screen at some moment, possibly partway between two themes
+#[derive(Clone, Debug)]
+struct Snapshot {
+    head: [f32; 3],
+    tail: Vec<[f32; 3]>,
+    background: [f32; 3],
+    accent: [f32; 3],
+    // Share of the per-column rainbow hue mixed into the tail gradient
+    rainbow: f32,
+}
+
+impl Snapshot {
+    fn of(theme: &Theme) -> Self {
+        Self {
+            head: theme.head,
+            tail: theme.tail.clone(),
+            background: theme.background,
+            accent: theme.accent,
+            rainbow: if theme.rainbow { 1.0 } else { 0.0 },
+        }
+    }
+
+    // The colors `t` of the way from `self` to `other`
+    fn mix(&self, other: &Snapshot, t: f32) -> Self {
+        // Each side's gradient only counts for the share not taken by the rainbow
+        let weights = ((1.0 - t) * (1.0 - self.rainbow), t * (1.0 - other.rainbow));
+        let share = weights.1 / (weights.0 + weights.1).max(f32::EPSILON);
+        let tail = (0..SNAPSHOT_TAIL_STOPS)
+            .map(|stop| {
+                let position = stop as f32 / (SNAPSHOT_TAIL_STOPS - 1) as f32;
+                let from = gradient(&self.tail, position);
+                lerp(from, gradient(&other.tail, position), share)
+            })
+            .collect();
+        Self {
+            head: lerp(self.head, other.head, t),
+            tail,
+            background: lerp(self.background, other.background, t),
+            accent: lerp(self.accent, other.accent, t),
+            rainbow: self.rainbow + (other.rainbow - self.rainbow) * t,
+        }
+    }
+
+    fn color(&self, part: ThemePart, column: usize) -> [f32; 3] {
+        match part {
+            ThemePart::Head => self.head,
+            ThemePart::Accent => self.accent,
+            ThemePart::Tail(t) if self.rainbow > 0.0 => {
+                let hue = hue_color((column as f32 * RAINBOW_HUE_STEP).fract());
+                lerp(gradient(&self.tail, t), hue, self.rainbow)
+            }
+            ThemePart::Tail(t) => gradient(&self.tail, t),
+        }
+    }
+}
+
+// The active theme, cross-fading from the previous colors after a switch
+pub struct Palette {
+    current: Theme,
+    previous: Option<Snapshot>,
+    started: Instant,
+    transition: Duration,
+    // Share of the current theme, updated once per frame
+    blend: f32,
+}
+
+impl Palette {
+    pub fn new(theme: Theme, transition: Duration) -> Self {
+        Self {
+            current: theme,
+            previous: None,
+            started: Instant::now(),
+            transition,
+            blend: 1.0,
+        }
+    }
+
+    // Switch to `theme`, fading from the colors currently on screen
+    pub fn set_theme(&mut self, theme: Theme, now: Instant) {
+        self.update(now);
+        // Switching mid-transition fades from the blend on screen, so colors don't jump
+        let current = Snapshot::of(&self.current);
+        let previous = match &self.previous {
+            Some(previous) => previous.mix(&current, self.blend),
+            None => current,
+        };
+        self.current = theme;
+        if self.transition.is_zero() {
+            return;
+        }
+        self.previous = Some(previous);
+        self.started = now;
+        self.blend = 0.0;
+    }
+
+    pub fn update(&mut self, now: Instant) {
+        if self.previous.is_none() {
+            return;
+        }
+        let elapsed = now.duration_since(self.started);
+        if elapsed >= self.transition {
+            self.previous = None;
+            self.blend = 1.0;
+        } else {
+            self.blend = elapsed.as_secs_f32() / self.transition.as_secs_f32();
+        }
+    }
+
+    fn mix(&self, current: [f32; 3], previous: impl Fn(&Snapshot) -> [f32; 3]) -> [f32; 3] {
+        match &self.previous {
+            Some(snapshot) => lerp(previous(snapshot), current, self.blend),
+            None => current,
+        }
+    }
+
+    pub fn color(&self, part: ThemePart, column: usize) -> [f32; 3] {
+        let current = self.current.color(part, column);
+        self.mix(current, |snapshot| snapshot.color(part, column))
+    }
+
+    pub fn background(&self) -> [f32; 3] {
+        self.mix(self.current.background, |snapshot| snapshot.background)
+    }
+}
+
+// Color at `t` (0 to 1) along evenly spaced gradient stops
+fn gradient(stops: &[[f32; 3]], t: f32) -> [f32; 3] {
+    match stops {
+        [] => [1.0, 1.0, 1.0],
+        [only] => *only,
+        _ => {
+            let position = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+            let index = (position.floor() as usize).min(stops.len() - 2);
+            lerp(stops[index], stops[index + 1], position - index as f32)
+        }
+    }
+}
+
+// Fully saturated color of a hue from 0 to 1
+fn hue_color(hue: f32) -> [f32; 3] {
+    let channel = |offset: f32| {
+        let k = (hue * 6.0 + offset) % 6.0;
+        1.0 - (k.min(4.0 - k).clamp(0.0, 1.0))
+    };
+    [channel(5.0), channel(3.0), channel(1.0)]
+}
+
+fn lerp(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [
+        a[0] + (b[0] - a[0]) * t,
+        a[1] + (b[1] - a[1]) * t,
+        a[2] + (b[2] - a[2]) * t,
+    ]
+}
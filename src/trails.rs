@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::glyph_quads::GlyphQuads;
+
+// Below this brightness factor a lingering glyph is dropped
+const MIN_BRIGHTNESS: f32 = 1.0 / 256.0;
+
+// The glyph a drop last left in a cell
+#[derive(Clone, Copy, Debug)]
+struct TrailCell {
+    ch: char,
+    transform: u32,
+    color: [f32; 4],
+    touched: Instant,
+}
+
+// Cells keep the last glyph a drop drew in them and fade it out over a
+// half-life once the drop has passed, until another drop overwrites them
+pub struct TrailDecay {
+    // Keyed by the cell's x in pixels and its row
+    cells: HashMap<(usize, i32), TrailCell>,
+    // Seconds; zero disables lingering
+    half_life: f32,
+    // Time of the current frame
+    now: Instant,
+}
+
+impl TrailDecay {
+    pub fn new(half_life: f32) -> Self {
+        Self {
+            cells: HashMap::new(),
+            half_life: half_life.max(0.0),
+            now: Instant::now(),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.half_life > 0.0
+    }
+
+    // Start a frame at `now` and forget glyphs that have faded out
+    pub fn begin_frame(&mut self, now: Instant) {
+        self.now = now;
+        if !self.enabled() {
+            return;
+        }
+        let half_life = self.half_life;
+        self.cells
+            .retain(|_, cell| fade(now, cell.touched, half_life) >= MIN_BRIGHTNESS);
+    }
+
+    // Record the glyph a drop draws in a cell this frame
+    pub fn touch(&mut self, x: usize, row: i32, ch: char, transform: u32, color: [f32; 4]) {
+        if !self.enabled() {
+            return;
+        }
+        self.cells.insert(
+            (x, row),
+            TrailCell {
+                ch,
+                transform,
+                color,
+                touched: self.now,
+            },
+        );
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+    }
+
+    // Cells no drop covers this frame, with their faded color
+    fn lingering(&self) -> impl Iterator<Item = (&(usize, i32), &TrailCell, f32)> + '_ {
+        self.cells
+            .iter()
+            .filter(|(_, cell)| cell.touched != self.now)
+            .map(|(key, cell)| (key, cell, fade(self.now, cell.touched, self.half_life)))
+    }
+
+    // Characters still fading, used to fill the on-demand glyph cache
+    pub fn visible_chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.lingering().map(|(_, cell, _)| cell.ch)
+    }
+
+    // Draw the fading glyphs in rows glyph_size pixels tall
+    pub fn push_quads(&self, quads: &mut GlyphQuads, glyph_size: f32) {
+        for (&(x, row), cell, brightness) in self.lingering() {
+            let color = cell.color.map(|channel| channel * brightness);
+            quads.push(
+                cell.ch,
+                cell.transform,
+                x as f32,
+                row as f32 * glyph_size,
+                color,
+            );
+        }
+    }
+}
+
+// Brightness factor of a glyph last touched at `touched`
+fn fade(now: Instant, touched: Instant, half_life: f32) -> f32 {
+    let elapsed = now.duration_since(touched).as_secs_f32();
+    0.5f32.powf(elapsed / half_life)
+}
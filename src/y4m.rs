@@ -0,0 +1,310 @@
+use image::{Rgba, RgbaImage};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+// Frame rate assumed when the header has none
+const DEFAULT_FPS: (u32, u32) = (25, 1);
+
+// How the chroma planes are subsampled
+#[derive(Clone, Copy, Debug)]
+enum Chroma {
+    C420,
+    C422,
+    C444,
+    // Luma only, drawn in greyscale
+    Mono,
+}
+
+impl Chroma {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "420" | "420jpeg" | "420paldv" | "420mpeg2" => Ok(Chroma::C420),
+            "422" => Ok(Chroma::C422),
+            "444" => Ok(Chroma::C444),
+            "mono" => Ok(Chroma::Mono),
+            other => Err(format!(
+                "Unsupported Y4M colorspace {:?} (expected 8-bit 420, 422, 444 or mono)",
+                other
+            )),
+        }
+    }
+
+    // Horizontal and vertical chroma subsampling shifts
+    fn shift(self) -> (u32, u32) {
+        match self {
+            Chroma::C420 => (1, 1),
+            Chroma::C422 => (1, 0),
+            Chroma::C444 | Chroma::Mono => (0, 0),
+        }
+    }
+}
+
+// Reads the frames of an uncompressed YUV4MPEG2 video, looping at the end
+pub struct Y4mReader {
+    reader: BufReader<File>,
+    width: u32,
+    height: u32,
+    chroma: Chroma,
+    frame_time: Duration,
+    // Offset of the first FRAME header, to loop back to
+    first_frame: u64,
+    // Y, U and V planes of the frame being decoded
+    planes: Vec<u8>,
+}
+
+impl Y4mReader {
+    // Frames wider or taller than `max_size` are rejected, as they couldn't be
+    // uploaded to a texture
+    pub fn open(path: &Path, max_size: u32) -> Result<Self, String> {
+        let file =
+            File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let mut reader = BufReader::new(file);
+        let mut header = String::new();
+        reader
+            .read_line(&mut header)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mut params = header.trim_end().split(' ');
+        if params.next() != Some("YUV4MPEG2") {
+            return Err(format!("{} is not a Y4M video", path.display()));
+        }
+
+        let (mut width, mut height) = (0, 0);
+        let mut chroma = Chroma::C420;
+        let mut fps = DEFAULT_FPS;
+        for param in params.filter(|param| !param.is_empty()) {
+            let mut chars = param.chars();
+            let tag = chars.next();
+            let value = chars.as_str();
+            let invalid = || format!("Invalid Y4M header field {:?} in {}", param, path.display());
+            match tag {
+                Some('W') => width = value.parse().map_err(|_| invalid())?,
+                Some('H') => height = value.parse().map_err(|_| invalid())?,
+                Some('C') => chroma = Chroma::parse(value)?,
+                Some('F') => {
+                    let (numerator, denominator) = value.split_once(':').ok_or_else(invalid)?;
+                    fps = (
+                        numerator.parse().map_err(|_| invalid())?,
+                        denominator.parse().map_err(|_| invalid())?,
+                    );
+                }
+                // Interlacing, aspect ratio and extensions don't change how frames are read
+                _ => {}
+            }
+        }
+        if width == 0 || height == 0 {
+            return Err(format!("{} has no frame size", path.display()));
+        }
+        if width > max_size || height > max_size {
+            return Err(format!(
+                "{} is {}x{}, larger than the {} pixel texture limit",
+                path.display(),
+                width,
+                height,
+                max_size
+            ));
+        }
+        if fps.0 == 0 || fps.1 == 0 {
+            return Err(format!("{} has an invalid frame rate", path.display()));
+        }
+
+        let first_frame = reader
+            .stream_position()
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mut video = Self {
+            reader,
+            width,
+            height,
+            chroma,
+            frame_time: Duration::from_secs_f64(fps.1 as f64 / fps.0 as f64),
+            first_frame,
+            planes: Vec::new(),
+        };
+        video.planes = vec![0; video.frame_bytes()];
+        Ok(video)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn frame_time(&self) -> Duration {
+        self.frame_time
+    }
+
+    fn chroma_size(&self) -> (u32, u32) {
+        let (shift_x, shift_y) = self.chroma.shift();
+        (
+            self.width.div_ceil(1 << shift_x),
+            self.height.div_ceil(1 << shift_y),
+        )
+    }
+
+    fn luma_bytes(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    fn chroma_bytes(&self) -> usize {
+        let (chroma_width, chroma_height) = self.chroma_size();
+        chroma_width as usize * chroma_height as usize
+    }
+
+    fn frame_bytes(&self) -> usize {
+        match self.chroma {
+            Chroma::Mono => self.luma_bytes(),
+            _ => self.luma_bytes() + 2 * self.chroma_bytes(),
+        }
+    }
+
+    // Decode the next frame, starting over after the last one
+    pub fn next_frame(&mut self) -> Result<RgbaImage, String> {
+        if !self.read_frame_header()? {
+            self.reader
+                .seek(SeekFrom::Start(self.first_frame))
+                .map_err(|e| format!("Failed to rewind video: {}", e))?;
+            if !self.read_frame_header()? {
+                return Err("Video has no frames".to_string());
+            }
+        }
+        self.reader
+            .read_exact(&mut self.planes)
+            .map_err(|e| format!("Truncated video frame: {}", e))?;
+        Ok(self.to_rgba())
+    }
+
+    // Consume a FRAME line; false at the end of the file
+    fn read_frame_header(&mut self) -> Result<bool, String> {
+        let mut line = Vec::new();
+        let read = self
+            .reader
+            .read_until(b'\n', &mut line)
+            .map_err(|e| format!("Failed to read video frame: {}", e))?;
+        if read == 0 {
+            return Ok(false);
+        }
+        if !line.starts_with(b"FRAME") {
+            return Err("Corrupt video frame header".to_string());
+        }
+        Ok(true)
+    }
+
+    // BT.601 studio-range YUV to RGB
+    fn to_rgba(&self) -> RgbaImage {
+        let (shift_x, shift_y) = self.chroma.shift();
+        let (chroma_width, _) = self.chroma_size();
+        let luma = &self.planes[..self.luma_bytes()];
+        let (u_plane, v_plane) = match self.chroma {
+            Chroma::Mono => (&[][..], &[][..]),
+            _ => self.planes[luma.len()..].split_at(self.chroma_bytes()),
+        };
+
+        RgbaImage::from_fn(self.width, self.height, |x, y| {
+            let y_value = luma[y as usize * self.width as usize + x as usize] as f32;
+            let chroma_index =
+                (y >> shift_y) as usize * chroma_width as usize + (x >> shift_x) as usize;
+            let u = u_plane.get(chroma_index).map_or(0.0, |&u| u as f32 - 128.0);
+            let v = v_plane.get(chroma_index).map_or(0.0, |&v| v as f32 - 128.0);
+            let c = 1.164 * (y_value - 16.0);
+            let channel = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+            Rgba([
+                channel(c + 1.596 * v),
+                channel(c - 0.392 * u - 0.813 * v),
+                channel(c + 2.017 * u),
+                255,
+            ])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // Write `contents` to a file only this test uses
+    fn video_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("matrix-{}-{}.y4m", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn open(name: &str, contents: &[u8]) -> Result<Y4mReader, String> {
+        let path = video_file(name, contents);
+        let video = Y4mReader::open(&path, 8192);
+        std::fs::remove_file(path).unwrap();
+        video
+    }
+
+    #[test]
+    fn parses_header() {
+        let video = open(
+            "header",
+            b"YUV4MPEG2 W4 H2 F30000:1001 Ip A1:1 C444 XYSCSS=444\n",
+        )
+        .unwrap();
+        assert_eq!((video.width(), video.height()), (4, 2));
+        assert_eq!(
+            video.frame_time(),
+            Duration::from_secs_f64(1001.0 / 30000.0)
+        );
+        assert_eq!(video.frame_bytes(), 4 * 2 * 3);
+    }
+
+    #[test]
+    fn defaults_frame_rate_and_colorspace() {
+        let video = open("defaults", b"YUV4MPEG2 W3 H3\n").unwrap();
+        assert_eq!(video.frame_time(), Duration::from_millis(40));
+        // 2x2 chroma planes for an odd-sized 420 frame
+        assert_eq!(video.frame_bytes(), 9 + 2 * 4);
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        assert!(open("magic", b"YUV4MPEG W4 H4\n").is_err());
+        assert!(open("no-width", b"YUV4MPEG2 H4\n").is_err());
+        assert!(open("no-height", b"YUV4MPEG2 W4\n").is_err());
+        assert!(open("bad-width", b"YUV4MPEG2 Wabc H4\n").is_err());
+        assert!(open("zero-fps", b"YUV4MPEG2 W4 H4 F0:1\n").is_err());
+        assert!(open("zero-denominator", b"YUV4MPEG2 W4 H4 F25:0\n").is_err());
+        assert!(open("bad-fps", b"YUV4MPEG2 W4 H4 F25\n").is_err());
+        assert!(open("colorspace", b"YUV4MPEG2 W4 H4 C420p10\n").is_err());
+    }
+
+    #[test]
+    fn rejects_frames_over_the_texture_limit() {
+        assert!(open("huge", b"YUV4MPEG2 W70000 H70000\n").is_err());
+        let path = video_file("limit", b"YUV4MPEG2 W16 H8\n");
+        assert!(Y4mReader::open(&path, 16).is_ok());
+        assert!(Y4mReader::open(&path, 15).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn loops_at_end_of_file() {
+        let mut contents = b"YUV4MPEG2 W1 H1 Cmono\n".to_vec();
+        contents.extend_from_slice(b"FRAME\n\x10FRAME\n\xeb");
+        let path = video_file("loop", &contents);
+        let mut video = Y4mReader::open(&path, 8192).unwrap();
+        let frames: Vec<_> = (0..5)
+            .map(|_| video.next_frame().unwrap()[(0, 0)])
+            .collect();
+        std::fs::remove_file(path).unwrap();
+
+        let black = Rgba([0, 0, 0, 255]);
+        let white = Rgba([255, 255, 255, 255]);
+        assert_eq!(frames, [black, white, black, white, black]);
+    }
+
+    #[test]
+    fn reports_missing_frames() {
+        let mut video = open("empty", b"YUV4MPEG2 W1 H1 Cmono\n").unwrap();
+        assert!(video.next_frame().is_err());
+        let mut video = open("truncated", b"YUV4MPEG2 W2 H2 Cmono\nFRAME\n\x10").unwrap();
+        assert!(video.next_frame().is_err());
+    }
+}